@@ -0,0 +1,333 @@
+use std::{path::PathBuf, str::FromStr};
+
+use serenity::all::Message;
+
+use crate::{
+    commands::{self, UploadOptions},
+    config::{config_path, ConfigError, ConfigValue, Settings},
+    error::{DistoreError, Result},
+    parser::{FileEntry, MANIFEST_HEADER},
+    pool::HttpPool,
+};
+
+/// A file stored on Discord.
+#[derive(Clone, Debug)]
+pub struct Entry {
+    /// ID of the head message, which identifies the file
+    pub id: u64,
+    /// Name of the file. It's read from the channel, so check it before
+    /// using it as a path
+    pub name: String,
+    pub size: u64,
+    /// Everything the head message records about the file
+    pub manifest: FileEntry,
+}
+
+impl Entry {
+    fn new(id: u64, manifest: FileEntry) -> Result<Self> {
+        let name = manifest
+            .name
+            .clone()
+            .ok_or(DistoreError::IncompleteManifest("name"))?;
+        let size = manifest
+            .size
+            .ok_or(DistoreError::IncompleteManifest("size"))?;
+        Ok(Self {
+            id,
+            name,
+            size,
+            manifest,
+        })
+    }
+
+    fn from_message(msg: &Message) -> Result<Self> {
+        if !msg.content.starts_with(MANIFEST_HEADER) {
+            return Err(DistoreError::NotAManifest(msg.id.get()));
+        }
+        Self::new(msg.id.get(), FileEntry::from_str(&msg.content)?)
+    }
+}
+
+/// Client for embedding distore in other programs. Unlike the functions in
+/// [`commands`], it doesn't print anything or set up a logger.
+///
+/// ```no_run
+/// # async fn run() -> libdistore::error::Result<()> {
+/// use libdistore::client::Distore;
+///
+/// let distore = Distore::builder()
+///     .token("<TOKEN>")
+///     .channel(123456789012345678)
+///     .build()?;
+/// let entry = distore.upload("backup.tar").await?;
+/// distore.download(entry.id, "restored.tar").await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Distore {
+    http: HttpPool,
+    channels: Vec<u64>,
+    options: UploadOptions,
+}
+
+#[derive(Default)]
+pub struct DistoreBuilder {
+    tokens: Vec<String>,
+    channels: Vec<u64>,
+    settings: Settings,
+    options: UploadOptions,
+}
+
+impl DistoreBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts from the tokens, channels and settings the CLI would use, read
+    /// from the config in `dir` or in the default config directory.
+    pub fn from_config(dir: Option<PathBuf>, remote: Option<&str>) -> Result<Self> {
+        let path = config_path(dir)?;
+        let (token, channel) = ConfigValue::resolve(&path, None, None, remote)?;
+        Ok(Self {
+            tokens: token.tokens(),
+            channels: channel.channels()?,
+            settings: ConfigValue::get_current_settings(&path, remote)?,
+            options: commands::get_upload_options_internal(&path, remote)?,
+        })
+    }
+
+    /// Adds the token of a bot. Requests are spread across every bot added.
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        self.tokens.push(token.into());
+        self
+    }
+
+    /// Adds a channel. Uploads are striped across every channel added, and
+    /// the first one holds the list of files.
+    pub fn channel(mut self, channel: u64) -> Self {
+        self.channels.push(channel);
+        self
+    }
+
+    /// Sets the API base, proxy and number of parts transferred at once. The
+    /// part size, cache directory, threads and compression that are set
+    /// also apply to uploads, unless [`Self::upload_options`] is called after.
+    pub fn settings(mut self, settings: Settings) -> Self {
+        if settings.part_size.is_some() {
+            self.options.part_size = settings.part_size;
+        }
+        if settings.cache_dir.is_some() {
+            self.options.cache_dir = settings.cache_dir.clone();
+        }
+        if let Some(threads) = settings.threads {
+            self.options.thread = threads;
+        }
+        if let Some(compression) = settings.compression {
+            self.options.compression = compression;
+        }
+        self.settings = settings;
+        self
+    }
+
+    /// Sets how files are uploaded, replacing what [`Self::settings`] set.
+    pub fn upload_options(mut self, options: UploadOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Fails if no channel was added, or if a setting or upload option is out
+    /// of range, like a part size or concurrency of 0.
+    pub fn build(self) -> Result<Distore> {
+        if self.channels.is_empty() {
+            return Err(ConfigError::NoChannel.into());
+        }
+        self.settings.validate()?;
+        if let Some(part_size) = self.options.part_size {
+            Settings::check("part_size", &part_size.to_string())?;
+        }
+
+        Ok(Distore {
+            http: HttpPool::with_settings(&self.tokens, &self.settings)?,
+            channels: self.channels,
+            options: self.options,
+        })
+    }
+}
+
+impl Distore {
+    pub fn builder() -> DistoreBuilder {
+        DistoreBuilder::new()
+    }
+
+    pub async fn upload(&self, file: impl Into<PathBuf>) -> Result<Entry> {
+        let messages = commands::upload_internal(
+            &self.http,
+            file.into(),
+            &self.channels,
+            &self.options,
+            |_, _| {},
+        )
+        .await?;
+        let head = messages.first().ok_or(DistoreError::NothingUploaded)?;
+        Entry::from_message(head)
+    }
+
+    /// Downloads the file `id` to `output`, falling back to its replicas if
+    /// it can't be read. Returns the path it was written to.
+    pub async fn download(&self, id: u64, output: impl Into<PathBuf>) -> Result<PathBuf> {
+        commands::download_internal(
+            &self.http,
+            id,
+            self.channels[0],
+            Some(output.into()),
+            |_| {},
+        )
+        .await
+    }
+
+    pub async fn list(&self) -> Result<Vec<Entry>> {
+        commands::list_internal(self.channels[0], &self.http)
+            .await?
+            .into_iter()
+            .map(|(manifest, id)| Entry::new(id, manifest))
+            .collect()
+    }
+
+    pub async fn delete(&self, id: u64) -> Result<()> {
+        commands::delete_internal(&self.http, id, self.channels[0], || {}).await
+    }
+
+    /// Reads what's recorded about the file `id` without downloading it. The
+    /// parts of files with many parts are read from their part index, so the
+    /// manifest always lists every part.
+    pub async fn stat(&self, id: u64) -> Result<Entry> {
+        let msg = commands::_get_head(&self.http, self.channels[0], id).await?;
+        let mut entry = Entry::from_message(&msg)?;
+        commands::_load_index(&self.http, &msg, &mut entry.manifest).await?;
+        Ok(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{
+        fake_discord,
+        parser::PartRef,
+        testing::{run, scratch},
+    };
+
+    fn client(api_base: String, dir: &Path) -> Distore {
+        Distore::builder()
+            .token("NQ.x.y")
+            .channel(10)
+            .settings(Settings {
+                api_base: Some(api_base),
+                part_size: Some(400),
+                cache_dir: Some(dir.join("cache")),
+                ..Default::default()
+            })
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn upload_returns_entry() {
+        let dir = scratch("client-upload");
+        let file = dir.join("notes.txt");
+        fs::write(&file, [7; 1000]).unwrap();
+
+        // An upload small enough for a single message sends the parts, then
+        // edits the message to hold the manifest
+        let mut attachments = Vec::new();
+        let base = fake_discord::start(move |request| {
+            let response = if request.method == "POST" {
+                attachments = fake_discord::attachments(&request.body, 1);
+                fake_discord::message(2, "", &attachments)
+            } else {
+                let edit: Value = serde_json::from_slice(&request.body).unwrap_or_default();
+                let content = edit["content"].as_str().unwrap_or_default();
+                fake_discord::message(2, content, &attachments)
+            };
+            response.to_string()
+        })
+        .unwrap();
+
+        let entry = run(client(base, &dir).upload(&file)).unwrap();
+
+        assert_eq!(entry.id, 2);
+        assert_eq!(entry.name, "notes.txt");
+        assert_eq!(entry.size, 1000);
+        // The part size of the settings was used
+        assert_eq!(entry.manifest.hashes.len(), 3);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn build_rejects_zero_sizes() {
+        let builder = || Distore::builder().token("NQ.x.y").channel(10);
+        let concurrency = builder().settings(Settings {
+            concurrency: Some(0),
+            ..Default::default()
+        });
+        let part_size = builder().upload_options(UploadOptions {
+            part_size: Some(0),
+            ..Default::default()
+        });
+
+        for builder in [concurrency, part_size] {
+            assert!(matches!(
+                builder.build(),
+                Err(DistoreError::Config(ConfigError::OutOfRange(..)))
+            ));
+        }
+    }
+
+    #[test]
+    fn stat_reads_the_index() {
+        let dir = scratch("client-stat");
+        let parts: Vec<_> = (0..3)
+            .map(|i| PartRef {
+                id: 2 + i,
+                offset: i * 400,
+                channel: None,
+            })
+            .collect();
+        let head = FileEntry {
+            name: Some("notes.txt".into()),
+            size: Some(1000),
+            len: Some(3),
+            index: Some(9),
+            ..Default::default()
+        };
+        let index = FileEntry {
+            parts: parts.clone(),
+            ..Default::default()
+        };
+
+        let base = fake_discord::start(move |request| match request.path.as_str() {
+            "/api/v10/channels/10/messages/2" => {
+                fake_discord::message(2, &head.to_string(), &[]).to_string()
+            }
+            "/api/v10/channels/10/messages/9" => {
+                let url = format!("{}/index", request.base);
+                let attachment = json!({"id": "1", "filename": "notes.txt.index", "size": 1,
+                    "url": url, "proxy_url": url});
+                fake_discord::message(9, "", &[attachment]).to_string()
+            }
+            "/index" => index.to_string(),
+            _ => "{}".into(),
+        })
+        .unwrap();
+
+        let entry = run(client(base, &dir).stat(2)).unwrap();
+
+        assert_eq!(entry.name, "notes.txt");
+        assert_eq!(entry.manifest.parts, parts);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
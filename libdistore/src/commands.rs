@@ -1,31 +1,69 @@
 use std::{
+    collections::{HashMap, HashSet},
     env,
-    fs::{self, File},
-    io::{Read, Write},
-    path::PathBuf,
+    fs::{self, File, OpenOptions},
+    io::{self, IsTerminal, Read, Write},
+    net::SocketAddr,
+    ops::Range,
+    path::{Path, PathBuf},
     process::{Command, Stdio},
     str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
 };
 
 use crate::{
-    config::{ConfigError, ConfigValue},
-    parser::FileEntry,
+    config::{config_path, ConfigError, ConfigValue, Settings},
+    erasure::ReedSolomon,
+    error::{DistoreError, Result},
+    parser::{FileEntry, MessageRef, PartRef, MANIFEST_HEADER},
+    pool::HttpPool,
 };
-use anyhow::{anyhow, Context, Result};
 use colored::Colorize;
-use futures::future::join_all;
+use flate2::{
+    write::{DeflateDecoder, DeflateEncoder},
+    Compression,
+};
+use futures::{future::join_all, stream, StreamExt, TryStreamExt};
 use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
 use indicatif_log_bridge::LogWrapper;
 use lazy_static::lazy_static;
-use log::info;
-use reqwest::Client;
+use log::{info, warn};
+use reqwest::{header::RANGE, Client, StatusCode};
+use ring::digest;
 use semver::Version;
 use serde_json::Value;
-use serenity::all::{
-    ChannelId, CreateAttachment, CreateMessage, EditMessage, GetMessages, Http, Message,
+use serenity::{
+    all::{
+        Attachment, AttachmentId, Channel, ChannelId, ChannelType, CreateAttachment, CreateChannel,
+        CreateForumPost, CreateMessage, CreateThread, EditAttachments, EditMessage, GetMessages,
+        GuildChannel, GuildId, GuildInfo, GuildPagination, Http, Message, PermissionOverwrite,
+        PermissionOverwriteType, Permissions, Timestamp, UserId,
+    },
+    http::Route,
 };
 
-static PART_SIZE: usize = 1000 * 1000 * 20;
+pub(crate) static PART_SIZE: usize = 1000 * 1000 * 20;
+static PARTS_PER_MESSAGE: usize = 10;
+pub(crate) static CONCURRENCY: usize = 4;
+/// Compression of the files uploaded with `compression` set
+pub(crate) const DEFLATE: &str = "deflate";
+static MESSAGE_LIMIT: usize = 2000;
+/// Number of data parts covered by each group of parity parts
+static PARITY_GROUP: usize = 10;
+/// Block size used when computing parity parts, to avoid loading whole parts
+static PARITY_BLOCK: usize = 1024 * 1024;
+/// Orphaned parts younger than this, in seconds, are left alone by gc since
+/// they may belong to an upload that is still running
+static GC_MIN_AGE: i64 = 60 * 60;
+/// Permissions the bot needs in the channel to store files in it
+static REQUIRED_PERMISSIONS: Permissions = Permissions::VIEW_CHANNEL
+    .union(Permissions::SEND_MESSAGES)
+    .union(Permissions::ATTACH_FILES)
+    .union(Permissions::READ_MESSAGE_HISTORY)
+    .union(Permissions::MANAGE_MESSAGES);
 
 lazy_static! {
     static ref VERSION: Version = {
@@ -43,62 +81,487 @@ lazy_static! {
     };
 }
 
-pub fn config(global: bool, key: String, val: String, dir: Option<PathBuf>) -> Result<()> {
+pub fn config(
+    global: bool,
+    key: String,
+    val: String,
+    encrypt: bool,
+    remote: Option<String>,
+    dir: Option<PathBuf>,
+) -> Result<()> {
     let conf = ConfigValue::parse(key, val)?;
+    let scope = _scope(global, remote)?;
+
+    let path = config_path(dir)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    ConfigValue::write_to_path(path.as_path(), &conf, scope, encrypt)?;
+    println!("Set \"{}\"", conf);
+    Ok(())
+}
+
+/// Sets up the token and channel after checking them with Discord. Whatever
+/// isn't given is asked for, as long as there's a terminal to ask in.
+pub async fn init(
+    token: Option<String>,
+    guild: Option<u64>,
+    channel: Option<u64>,
+    encrypt: bool,
+    remote: Option<String>,
+    dir: Option<PathBuf>,
+) -> Result<()> {
+    let token = match token {
+        Some(token) => token,
+        None if io::stdin().is_terminal() => rpassword::prompt_password("Token: ")?,
+        None => return Err(DistoreError::NoTokenGiven),
+    };
+
+    // The remote may not exist until it's written below
+    let path = config_path(dir.clone())?;
+    let settings = ConfigValue::get_current_settings(&path, remote.as_deref()).unwrap_or_default();
+    let http = HttpPool::with_settings(&[token.as_str()], &settings)?;
+
+    let user = http.get_current_user().await?;
+    println!("Logged in as {}", user.name.bold());
+
+    let channel = match channel {
+        Some(id) => match http.get_channel(id.into()).await? {
+            Channel::Guild(channel) => channel,
+            _ => return Err(DistoreError::NotAGuildChannel(id)),
+        },
+        None => {
+            let guild = match guild {
+                Some(id) => GuildId::new(id),
+                None => {
+                    let guilds = _get_guilds(&http).await?;
+                    let names = guilds.iter().map(|g| g.name.clone()).collect();
+                    guilds[_pick("server", "guild", names)?].id
+                }
+            };
+
+            // Only offer the channels the bot can already store files in
+            let partial = http.get_guild(guild).await?;
+            let member = http.get_member(guild, user.id).await?;
+            let mut channels: Vec<_> = http
+                .get_channels(guild)
+                .await?
+                .into_iter()
+                .filter(|c| matches!(c.kind, ChannelType::Text | ChannelType::Forum))
+                .filter(|c| {
+                    partial
+                        .user_permissions_in(c, &member)
+                        .contains(REQUIRED_PERMISSIONS)
+                })
+                .collect();
+            if channels.is_empty() {
+                return Err(DistoreError::NoUsableChannel {
+                    guild: partial.name,
+                    needed: REQUIRED_PERMISSIONS
+                        .get_permission_names()
+                        .into_iter()
+                        .map(String::from)
+                        .collect(),
+                });
+            }
+            channels.sort_by_key(|c| c.position);
+            let names = channels
+                .iter()
+                .map(|c| match c.kind {
+                    ChannelType::Forum => format!("#{} (forum)", c.name),
+                    _ => format!("#{}", c.name),
+                })
+                .collect();
+            channels.swap_remove(_pick("channel", "channel", names)?)
+        }
+    };
+
+    let guild = http.get_guild(channel.guild_id).await?;
+    let member = http.get_member(channel.guild_id, user.id).await?;
+    let missing = REQUIRED_PERMISSIONS.difference(guild.user_permissions_in(&channel, &member));
+    if !missing.is_empty() {
+        return Err(DistoreError::MissingPermissions {
+            channel: channel.name,
+            missing: missing
+                .get_permission_names()
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        });
+    }
+
+    let mut values = vec![("token", token), ("channel", channel.id.to_string())];
+    // Forum channels only hold posts, so every upload needs a thread of its own
+    if channel.kind == ChannelType::Forum {
+        values.push(("threads", "true".into()));
+    }
+    for (key, val) in values {
+        config(true, key.into(), val, encrypt, remote.clone(), dir.clone())?;
+    }
+    println!(
+        "{} Files will be stored in #{}",
+        "Done.".green().bold(),
+        channel.name
+    );
+    Ok(())
+}
+
+/// Creates a category and a channel in it that only the bots of the pool and
+/// `roles` can see, and saves the channel to the config.
+pub async fn setup(
+    guild: u64,
+    roles: Vec<u64>,
+    category: String,
+    name: String,
+    token: Option<String>,
+    remote: Option<String>,
+    dir: Option<PathBuf>,
+) -> Result<()> {
+    let path = config_path(dir.clone())?;
+    let tokens = _tokens(&path, token, remote.as_deref())?;
+
+    let settings = ConfigValue::get_current_settings(&path, remote.as_deref())?;
+    let http = HttpPool::with_settings(&tokens, &settings)?;
+    let guild = GuildId::new(guild);
+
+    let mut bots = http.bot_ids();
+    if bots.is_empty() {
+        bots.push(http.get_current_user().await?.id.get());
+    }
+
+    // @everyone shares its ID with the guild
+    let mut overwrites = vec![PermissionOverwrite {
+        allow: Permissions::empty(),
+        deny: Permissions::VIEW_CHANNEL,
+        kind: PermissionOverwriteType::Role(guild.get().into()),
+    }];
+    overwrites.extend(bots.into_iter().map(|id| PermissionOverwrite {
+        allow: REQUIRED_PERMISSIONS,
+        deny: Permissions::empty(),
+        kind: PermissionOverwriteType::Member(id.into()),
+    }));
+    overwrites.extend(roles.into_iter().map(|id| PermissionOverwrite {
+        allow: Permissions::VIEW_CHANNEL | Permissions::READ_MESSAGE_HISTORY,
+        deny: Permissions::empty(),
+        kind: PermissionOverwriteType::Role(id.into()),
+    }));
+
+    let denied = |e: serenity::Error| match DistoreError::from(e) {
+        e if e.is_permission_denied() => DistoreError::CannotCreateChannel,
+        e => e,
+    };
+    let category = guild
+        .create_channel(
+            &*http,
+            CreateChannel::new(category)
+                .kind(ChannelType::Category)
+                .permissions(overwrites.clone()),
+        )
+        .await
+        .map_err(denied)?;
+    let channel = match guild
+        .create_channel(
+            &*http,
+            CreateChannel::new(name)
+                .kind(ChannelType::Text)
+                .category(category.id)
+                .permissions(overwrites),
+        )
+        .await
+    {
+        Ok(channel) => channel,
+        Err(e) => {
+            // Don't leave an empty category behind
+            if let Err(e) = http.delete_channel(category.id, None).await {
+                warn!("Failed to delete the category {}: {e}", category.name);
+            }
+            return Err(denied(e));
+        }
+    };
+
+    println!(
+        "{} #{} in {}",
+        "Created".green().bold(),
+        channel.name,
+        category.name
+    );
+    config(
+        true,
+        "channel".into(),
+        channel.id.to_string(),
+        false,
+        remote,
+        dir,
+    )
+}
+
+/// Resolves the tokens alone, for the commands that don't need a channel.
+fn _tokens(path: &Path, token: Option<String>, remote: Option<&str>) -> Result<Vec<String>> {
+    let token = match token {
+        Some(t) => ConfigValue::Token(t),
+        None => {
+            ConfigValue::get_current_value(path, "token", remote)?.ok_or(ConfigError::NoToken)?
+        }
+    };
+    Ok(token.tokens())
+}
+
+/// Sets up the logger of the commands that print their progress.
+fn _init_logger() {
+    colog::default_builder()
+        .filter(Some("serenity"), log::LevelFilter::Off)
+        .init();
+}
+
+/// Resolves the tokens and channels of a command from its flags and the config
+/// in `dir`, and connects with the settings that apply. Returns the pool, the
+/// channels and the settings.
+fn _connect(
+    token: Option<String>,
+    channel: Option<u64>,
+    remote: Option<&str>,
+    dir: Option<PathBuf>,
+) -> Result<(HttpPool, Vec<u64>, Settings)> {
+    let path = config_path(dir)?;
+    let (token, channel) = ConfigValue::resolve(&path, token, channel, remote)?;
+    let settings = ConfigValue::get_current_settings(&path, remote)?;
+    let http = HttpPool::with_settings(&token.tokens(), &settings)?;
+    Ok((http, channel.channels()?, settings))
+}
+
+/// Asks which of `options` to use and returns its index. Picks the only one
+/// without asking, and fails if there's no terminal to ask in.
+fn _pick(what: &str, flag: &str, options: Vec<String>) -> Result<usize> {
+    match options.len() {
+        0 => return Err(DistoreError::NothingToPick(what.into())),
+        1 => {
+            println!("Using the {what} {}", options[0].bold());
+            return Ok(0);
+        }
+        _ if !io::stdin().is_terminal() => {
+            return Err(DistoreError::AmbiguousPick {
+                what: what.into(),
+                flag: flag.into(),
+            })
+        }
+        _ => (),
+    }
+
+    for (i, option) in options.iter().enumerate() {
+        println!("{:>3}. {option}", i + 1);
+    }
+    loop {
+        print!("Pick a {what}: ");
+        io::stdout().flush()?;
+        let mut input = String::new();
+        io::stdin().read_line(&mut input)?;
+        match input.trim().parse::<usize>() {
+            Ok(i) if (1..=options.len()).contains(&i) => return Ok(i - 1),
+            _ => println!("Type a number between 1 and {}", options.len()),
+        }
+    }
+}
+
+/// Returns the section that `config` writes to: the one of `remote`, the
+/// global one, or the one of the current directory.
+fn _scope(global: bool, remote: Option<String>) -> Result<Option<String>> {
     let current_dir = env::current_dir()?;
-    let scope = match global {
-        true => None,
-        false => Some(
+    Ok(match (remote, global) {
+        (Some(remote), _) => Some(ConfigValue::remote_section(&remote)),
+        (None, true) => None,
+        (None, false) => Some(
             current_dir
                 .clone()
                 .to_str()
                 .ok_or(ConfigError::NonUnicodePath(current_dir))?
                 .to_string(),
         ),
+    })
+}
+
+pub fn config_get(
+    global: bool,
+    key: String,
+    remote: Option<String>,
+    dir: Option<PathBuf>,
+) -> Result<()> {
+    let settings = get_settings_internal(global, remote, dir)?;
+    let (_, val) = settings
+        .pairs()
+        .into_iter()
+        .find(|(k, _)| *k == key)
+        .ok_or(match Settings::KEYS.contains(&key.as_str()) {
+            true => DistoreError::NotSet(key.clone()),
+            false => ConfigError::InvalidKey(key.clone()).into(),
+        })?;
+
+    println!("{}", ConfigValue::parse(key, val)?);
+    Ok(())
+}
+
+pub fn config_unset(
+    global: bool,
+    key: String,
+    remote: Option<String>,
+    dir: Option<PathBuf>,
+) -> Result<()> {
+    let scope = _scope(global, remote)?;
+    let path = config_path(dir)?;
+
+    match ConfigValue::unset_from_path(&path, &key, scope.as_deref())? {
+        true => println!("Unset \"{key}\""),
+        false => println!("{key} wasn't set"),
+    }
+    Ok(())
+}
+
+pub fn config_list(global: bool, remote: Option<String>, dir: Option<PathBuf>) -> Result<()> {
+    let settings = get_settings_internal(global, remote, dir)?;
+    for (key, val) in settings.pairs() {
+        println!("{}", ConfigValue::parse(key, &val)?);
+    }
+    Ok(())
+}
+
+pub fn config_migrate(dir: Option<PathBuf>) -> Result<()> {
+    colog::default_builder().init();
+
+    let path = config_path(dir)?;
+    let Some(problems) = ConfigValue::migrate(&path)? else {
+        println!("{} has no older config to migrate", path.display());
+        return Ok(());
     };
 
-    let mut path = dir
-        .unwrap_or(dirs::config_dir().ok_or(ConfigError::NoConfigDir)?)
-        .join("distore");
-    fs::create_dir_all(&path).context("Failed to create config directory")?;
-    path.push("distore.ini");
+    for problem in problems.iter() {
+        warn!("Left out: {problem}");
+    }
+    println!(
+        "{} the config to {} with {} problem(s)",
+        "Migrated".green().bold(),
+        path.display(),
+        problems.len()
+    );
+    Ok(())
+}
+
+pub fn config_scopes(remove: Option<PathBuf>, dir: Option<PathBuf>) -> Result<()> {
+    let path = config_path(dir)?;
 
-    ConfigValue::write_to_path(path.as_path(), &conf, scope)
-        .context("Failed to write to the config file")?;
-    println!("Set \"{}\"", conf);
+    if let Some(scope) = remove {
+        let scope = env::current_dir()?.join(scope);
+        let name = scope
+            .to_str()
+            .ok_or(ConfigError::NonUnicodePath(scope.clone()))?;
+        match ConfigValue::remove_scope(&path, name.trim_end_matches(['/', '\\']))? {
+            true => println!("Removed the settings of {name}"),
+            false => println!("{name} has no settings of its own"),
+        }
+        return Ok(());
+    }
+
+    for (scope, keys) in ConfigValue::scopes(&path)? {
+        let missing = match Path::new(&scope).exists() {
+            true => "",
+            false => " (missing)",
+        };
+        println!("{}{missing}: {}", scope.bold(), keys.join(", "));
+    }
+    Ok(())
+}
+
+pub fn config_export(file: Option<PathBuf>, secrets: bool, dir: Option<PathBuf>) -> Result<()> {
+    let data = ConfigValue::export(&config_path(dir)?, secrets)?;
+
+    let Some(file) = file else {
+        print!("{data}");
+        return Ok(());
+    };
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(&file)?.write_all(data.as_bytes())?;
+
+    println!("{} {}", "Exported to".green().bold(), file.display());
+    Ok(())
+}
+
+/// Imports a config exported with `config_export`. A `file` of "-" reads it
+/// from stdin.
+pub fn config_import(file: PathBuf, dir: Option<PathBuf>) -> Result<()> {
+    let data = match file.to_str() {
+        Some("-") => io::read_to_string(io::stdin())?,
+        _ => fs::read_to_string(&file)?,
+    };
+
+    let path = config_path(dir)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    ConfigValue::import(&path, &data)?;
+
+    println!("{} {}", "Imported".green().bold(), file.display());
     Ok(())
 }
 
-pub fn get_config(global: bool, dir: Option<PathBuf>) -> Result<()> {
-    let (token, channel) = get_config_internal(global, dir)?;
+/// Returns the settings of `remote`, of the global section, or the ones that
+/// apply to the current directory.
+pub(crate) fn get_settings_internal(
+    global: bool,
+    remote: Option<String>,
+    dir: Option<PathBuf>,
+) -> Result<Settings> {
+    let path = config_path(dir)?;
+    Ok(match (remote, global) {
+        (Some(remote), _) => ConfigValue::get_current_settings(&path, Some(&remote))?,
+        (None, true) => ConfigValue::get_scope_settings(&path, None)?,
+        (None, false) => ConfigValue::get_current_settings(&path, None)?,
+    })
+}
+
+pub fn get_config(global: bool, remote: Option<String>, dir: Option<PathBuf>) -> Result<()> {
+    let remotes = get_remotes_internal(dir.clone())?;
+    let (token, channel) = get_config_internal(global, remote, dir)?;
 
     println!("{}", token);
     println!("{}", channel);
+    if !remotes.is_empty() {
+        println!("Remotes: {}", remotes.join(", "));
+    }
     Ok(())
 }
 
 pub(crate) fn get_config_internal(
     global: bool,
+    remote: Option<String>,
     dir: Option<PathBuf>,
 ) -> Result<(ConfigValue, ConfigValue)> {
-    let mut path = dir
-        .unwrap_or(dirs::config_dir().ok_or(ConfigError::NoConfigDir)?)
-        .join("distore");
-    fs::create_dir_all(&path).context("Failed to create config directory")?;
-    path.push("distore.ini");
-    let out = match global {
-        true => crate::config::ConfigValue::get_global_config(&path)?,
-        false => crate::config::ConfigValue::get_current_config(&path)?,
+    let path = config_path(dir)?;
+    let out = match (remote, global) {
+        (Some(remote), _) => crate::config::ConfigValue::get_current_config(&path, Some(&remote))?,
+        (None, true) => crate::config::ConfigValue::get_global_config(&path)?,
+        (None, false) => crate::config::ConfigValue::get_current_config(&path, None)?,
     };
     Ok(out)
 }
 
+/// Forgets the passphrase of the config for the rest of the session.
+pub fn lock() -> Result<()> {
+    crate::secret::lock().map_err(ConfigError::from)?;
+    println!("{} the config", "Locked".green().bold());
+    Ok(())
+}
+
+/// Returns the names of the remotes set in the config.
+pub(crate) fn get_remotes_internal(dir: Option<PathBuf>) -> Result<Vec<String>> {
+    Ok(ConfigValue::remotes(&config_path(dir)?)?)
+}
+
 pub fn disassemble(path: PathBuf, output: PathBuf) -> Result<()> {
-    colog::default_builder()
-        .filter(Some("serenity"), log::LevelFilter::Off)
-        .init();
+    _init_logger();
 
-    let (_, filename, i) = disassemble_internal(path, output, |_, _| {})?;
+    let (_, filename, i, _) = disassemble_internal(path, output, PART_SIZE, |_, _| {})?;
 
     println!(
         "{} {filename} into {i} parts",
@@ -110,44 +573,65 @@ pub fn disassemble(path: PathBuf, output: PathBuf) -> Result<()> {
 pub(crate) fn disassemble_internal<F: Fn(String, f64)>(
     path: PathBuf,
     output: PathBuf,
+    part_size: usize,
     callback: F,
-) -> Result<(Vec<PathBuf>, String, usize)> {
-    let mut file =
-        File::open(&path).with_context(|| format!("Cannot open file: {}", path.display()))?;
-    let filename = path.file_name().unwrap().to_str().unwrap().to_owned();
+) -> Result<(Vec<PathBuf>, String, usize, String)> {
+    let mut file = File::open(&path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("Cannot open file {}: {e}", path.display()),
+        )
+    })?;
+    let filename = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| DistoreError::InvalidFileName(path.clone()))?
+        .to_owned();
 
     let mut out = Vec::new();
+    let mut hash = digest::Context::new(&digest::SHA256);
 
-    let mut buf = vec![0; PART_SIZE];
+    let mut buf = vec![0; part_size];
 
     let mut progress = 0;
-    let total = (file.metadata().unwrap().len() + PART_SIZE as u64 - 1) / PART_SIZE as u64;
-    while let Ok(bytes_read) = file.read(&mut buf) {
-        if bytes_read == 0 {
-            break;
-        }
+    let total = file.metadata()?.len().div_ceil(part_size as u64);
+    let mut write_parts = || -> Result<()> {
+        loop {
+            let bytes_read = file.read(&mut buf)?;
+            if bytes_read == 0 {
+                break;
+            }
 
-        let name = format!("{}.part{}", filename, progress);
-        let path = output.join(&name);
-        let mut chunk = File::create(&path)?;
+            let name = format!("{}.part{}", filename, progress);
+            let path = output.join(&name);
+            out.push(path.clone());
+            let mut chunk = File::create(&path)?;
 
-        info!("{} {name}", "Writing".blue().bold());
-        chunk.write_all(&buf[..bytes_read])?;
-        progress += 1;
+            info!("{} {name}", "Writing".blue().bold());
+            chunk.write_all(&buf[..bytes_read])?;
+            hash.update(&buf[..bytes_read]);
+            progress += 1;
 
-        out.push(path);
-        let fraction = if total > 0 {
-            progress as f64 / total as f64
-        } else {
-            1.0
-        };
+            let fraction = if total > 0 {
+                progress as f64 / total as f64
+            } else {
+                1.0
+            };
 
-        let fraction = fraction.clamp(0.0, 1.0);
-        callback(format!("Disassembling {}", filename), fraction);
+            let fraction = fraction.clamp(0.0, 1.0);
+            callback(format!("Disassembling {}", filename), fraction);
+        }
+        Ok(())
+    };
+
+    // Don't leave half the parts behind
+    if let Err(e) = write_parts() {
+        _remove_parts(&out);
+        return Err(e);
     }
 
     let len = out.len();
-    Ok((out, filename, len))
+    Ok((out, filename, len, hex(hash.finish().as_ref())))
 }
 
 pub fn assemble(filename: String, path: PathBuf, output: Option<PathBuf>) -> Result<()> {
@@ -163,32 +647,25 @@ pub fn assemble(filename: String, path: PathBuf, output: Option<PathBuf>) -> Res
             continue;
         }
 
-        if !entry.file_name().to_str().unwrap().starts_with(&look_for) {
+        let name = entry.file_name();
+        let name = name
+            .to_str()
+            .ok_or_else(|| DistoreError::InvalidFileName(entry.path()))?;
+        // Other files may share the prefix, like 'notes.txt.partial'
+        let Some(Ok(index)) = name.strip_prefix(&look_for).map(str::parse::<usize>) else {
             continue;
-        }
+        };
 
-        parts.push(entry.path());
+        parts.push((index, entry.path()));
+    }
+    // Sort by the number, as the names put 'part10' before 'part2'
+    parts.sort_unstable_by_key(|(index, _)| *index);
+    if let Some(missing) = parts.iter().enumerate().find(|(i, (index, _))| i != index) {
+        return Err(DistoreError::Integrity(format!(
+            "{look_for}{} is missing",
+            missing.0
+        )));
     }
-    parts.sort_unstable_by(|a, b| {
-        let a_name = a
-            .file_name()
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .chars()
-            .last()
-            .unwrap();
-        let b_name = b
-            .file_name()
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .chars()
-            .last()
-            .unwrap();
-
-        a_name.partial_cmp(&b_name).unwrap()
-    });
 
     let mut out = File::create(output.clone().unwrap_or(path.clone().join(&filename)))?;
 
@@ -196,10 +673,8 @@ pub fn assemble(filename: String, path: PathBuf, output: Option<PathBuf>) -> Res
     let logger = colog::default_builder()
         .filter(Some("serenity"), log::LevelFilter::Off)
         .build();
-    LogWrapper::new(multi.clone(), logger)
-        .try_init()
-        .context("Failed to initilize logger")
-        .unwrap();
+    // A logger may already be set by whoever calls this
+    _ = LogWrapper::new(multi.clone(), logger).try_init();
     let pb = multi.add(ProgressBar::new(parts.len().try_into().unwrap()));
 
     pb.set_style(
@@ -213,10 +688,15 @@ pub fn assemble(filename: String, path: PathBuf, output: Option<PathBuf>) -> Res
 
     let amount = parts.len();
     let mut buf = Vec::new();
-    for part in parts {
+    for (_, part) in parts {
         info!("{} {}", "Writing".blue().bold(), part.display());
         buf.clear();
-        let mut part = File::open(part).unwrap();
+        let mut part = File::open(&part).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Cannot open part {}: {e}", part.display()),
+            )
+        })?;
         part.read_to_end(&mut buf)?;
         out.write_all(&buf)?;
         pb.inc(1);
@@ -233,199 +713,1002 @@ pub fn assemble(filename: String, path: PathBuf, output: Option<PathBuf>) -> Res
     Ok(())
 }
 
+/// Settings of an upload besides the file and the channels it goes to.
+#[derive(Default)]
+pub struct UploadOptions {
+    /// Channels to put an extra copy in, one copy each
+    pub replicas: Vec<u64>,
+    /// Parity parts to compute for every group of parts
+    pub parity: usize,
+    /// Put the upload in a thread of its own, or a post in forum channels
+    pub thread: bool,
+    /// Keep the parts sent when the upload fails, so that uploading the file
+    /// again continues from them
+    pub keep_partial: bool,
+    /// Size of the parts, if not the default one
+    pub part_size: Option<usize>,
+    /// Directory to write the parts to, if not the user's cache directory
+    pub cache_dir: Option<PathBuf>,
+    /// Compress the file before splitting it
+    pub compression: bool,
+}
+
+/// Where the messages of an upload are sent.
+#[derive(Clone, Copy)]
+enum Destination<'a> {
+    /// Striped across the channels in turn
+    Channels(&'a [u64]),
+    /// A new thread of the channel, named after the file
+    Thread(u64),
+}
+
+impl<'a> Destination<'a> {
+    /// Fails when asked for a thread with several channels, as a thread can't
+    /// be striped across channels.
+    fn new(channels: &'a [u64], thread: bool) -> Result<Self> {
+        match (thread, channels) {
+            (true, [channel]) => Ok(Self::Thread(*channel)),
+            (true, [_, _, ..]) => Err(DistoreError::ThreadStriping),
+            _ => Ok(Self::Channels(channels)),
+        }
+    }
+}
+
 pub async fn upload(
     file: PathBuf,
+    options: UploadOptions,
     token: Option<String>,
     channel: Option<u64>,
+    remote: Option<String>,
     dir: Option<PathBuf>,
 ) -> Result<()> {
-    colog::default_builder()
-        .filter(Some("serenity"), log::LevelFilter::Off)
-        .init();
-    let mut path = dir
-        .unwrap_or(dirs::config_dir().ok_or(ConfigError::NoConfigDir)?)
-        .join("distore");
-    fs::create_dir_all(&path).context("Failed to create config directory")?;
-    path.push("distore.ini");
-
-    let token = token.unwrap_or_else(|| {
-        crate::config::ConfigValue::get_current_config(&path)
-            .context("Failed to get the config file")
-            .unwrap()
-            .0
-            .inner()
-            .to_string()
-    });
-    let channel = channel.unwrap_or_else(|| {
-        crate::config::ConfigValue::get_current_config(&path)
-            .unwrap()
-            .1
-            .inner()
-            .parse()
-            .unwrap()
-    });
+    _init_logger();
+    let path = config_path(dir.clone())?;
+    let (http, channels, _) = _connect(token, channel, remote.as_deref(), dir)?;
 
-    let http = Http::new(&token);
+    let configured = get_upload_options_internal(&path, remote.as_deref())?;
+    let options = UploadOptions {
+        parity: options.parity,
+        thread: options.thread || configured.thread,
+        keep_partial: options.keep_partial,
+        ..configured
+    };
 
-    let messages = upload_internal(&http, file, channel, |_, _| {}).await?;
+    let messages = upload_internal(&http, file, &channels, &options, |_, _| {}).await?;
+    let head = messages.first().ok_or(DistoreError::NothingUploaded)?;
 
     println!(
         "{} parts to channel id {}. Message id: {}",
         "Uploaded".green().bold(),
-        messages[0].channel_id,
-        messages[0].id
+        head.channel_id,
+        head.id
     );
 
     Ok(())
 }
 
+/// Returns the channels to put the extra copies of an upload in, one for every
+/// copy the configured replication factor asks for.
+pub(crate) fn get_replicas_internal(path: &Path, remote: Option<&str>) -> Result<Vec<u64>> {
+    let settings = ConfigValue::get_current_settings(path, remote)?;
+    let factor = settings.replication.unwrap_or(1);
+    let replicas = match settings.replicas {
+        Some(r) => ConfigValue::Channel(r).channels()?,
+        None => Vec::new(),
+    };
+    let extra = factor.saturating_sub(1);
+    if replicas.len() < extra {
+        return Err(DistoreError::MissingReplicas {
+            factor,
+            needed: extra,
+            set: replicas.len(),
+        });
+    }
+
+    Ok(replicas[..extra].to_vec())
+}
+
+/// Reads the upload settings of the current directory, or of `remote` if one
+/// is given, from the config.
+pub(crate) fn get_upload_options_internal(
+    path: &Path,
+    remote: Option<&str>,
+) -> Result<UploadOptions> {
+    let settings = ConfigValue::get_current_settings(path, remote)?;
+    Ok(UploadOptions {
+        replicas: get_replicas_internal(path, remote)?,
+        thread: settings.threads.unwrap_or(false),
+        part_size: settings.part_size,
+        cache_dir: settings.cache_dir,
+        compression: settings.compression.unwrap_or(false),
+        ..Default::default()
+    })
+}
+
+/// Uploads a file, striping its messages across `channels` in turn, or into a
+/// new thread of the first channel. Unless `keep_partial` is set, every
+/// message sent and every cached part is removed again when the upload fails.
+/// Parts kept by an earlier upload of the same file aren't sent again.
 pub(crate) async fn upload_internal<F: Fn(String, f64)>(
-    http: &Http,
+    http: &HttpPool,
     file: PathBuf,
-    channel: u64,
+    channels: &[u64],
+    options: &UploadOptions,
     callback: F,
 ) -> Result<Vec<Message>> {
-    let cache_dir = dirs::cache_dir().unwrap().join("distore");
-    fs::create_dir_all(&cache_dir)?;
-    let (part_paths, filename, _) =
-        disassemble_internal(file.clone(), cache_dir.clone(), &callback)?;
-
-    let msg = format!(
-        "### This message is generated by Distore. Do not edit this message.\nname={}\nsize={}",
-        filename,
-        file.metadata()?.len()
-    );
-
-    info!("Uploading...");
-    let chunks: Vec<Vec<PathBuf>> = part_paths.chunks(10).map(|chunk| chunk.to_vec()).collect();
-    let mut messages = Vec::new();
-    info!("Sending {} message(s) in total", chunks.len());
+    let UploadOptions {
+        replicas,
+        parity,
+        thread,
+        keep_partial,
+        part_size,
+        cache_dir,
+        compression,
+    } = options;
+    let parity = *parity;
+    let part_size = part_size.unwrap_or(PART_SIZE);
 
-    callback(format!("Uploading {}", filename), 0.0);
-    let mut progress = 0;
-    let total = chunks.len();
-    for chunk in chunks {
-        let attachment_futures: Vec<_> = chunk.into_iter().map(CreateAttachment::path).collect();
-        let attachments = join_all(attachment_futures).await;
-
-        let msg = ChannelId::from(channel)
-            .send_files(
-                &http,
-                attachments.into_iter().map(|a| a.unwrap()),
-                CreateMessage::new().content("tmp"),
-            )
-            .await?;
-        messages.push(msg.clone());
-        progress += 1;
+    let destination = Destination::new(channels, *thread)?;
+    let cache_dir = _cache_dir(cache_dir.as_deref())?;
 
-        let fraction = if total > 0 {
-            progress as f64 / total as f64
-        } else {
-            1.0
+    // Every part file and message that exists so far, to roll back with
+    let mut files: Vec<PathBuf> = Vec::new();
+    let mut messages = Vec::new();
+    let mut threads = Vec::new();
+    // Messages of the first copy by the chunk they hold, to resume from
+    let mut sent = Vec::new();
+    let mut partial = None;
+    let res = async {
+        let source = match compression {
+            true => {
+                let compressed = _compress(&file, &cache_dir)?;
+                files.push(compressed.clone());
+                compressed
+            }
+            false => file.clone(),
         };
+        let (part_paths, filename, _, sha256) =
+            disassemble_internal(source.clone(), cache_dir.clone(), part_size, &callback)?;
+        files.extend(part_paths.iter().cloned());
+        if part_paths.is_empty() {
+            return Err(DistoreError::EmptyFile(file.clone()));
+        }
+        let parity_paths = match parity {
+            0 => Vec::new(),
+            _ => _write_parity_parts(&part_paths, parity, &cache_dir, &filename)?,
+        };
+        files.extend(parity_paths.iter().cloned());
 
-        let fraction = fraction.clamp(0.0, 1.0);
-        callback(format!("Uploading {}", filename), fraction);
-
-        info!(
-            "Sent {}..{}",
-            msg.attachments
-                .first()
-                .unwrap()
-                .filename
-                .split(".")
-                .last()
-                .unwrap(),
-            msg.attachments
-                .last()
-                .unwrap()
-                .filename
-                .split(".")
-                .last()
-                .unwrap()
-        );
-    }
+        let head = FileEntry {
+            name: Some(filename.clone()),
+            size: Some(source.metadata()?.len()),
+            len: Some(part_paths.len()),
+            part_size: Some(part_size as u64),
+            sha256: Some(sha256),
+            compression: compression.then(|| DEFLATE.to_string()),
+            original_size: compression.then_some(file.metadata()?.len()),
+            parity: Some(parity).filter(|p| *p > 0),
+            parity_group: Some(PARITY_GROUP).filter(|_| parity > 0),
+            ..Default::default()
+        };
+        partial = Some(head.clone());
 
-    info!("Editing messages...");
+        // Removed with the parts once the upload is done with it
+        let record = _partial_path(&cache_dir, &head)?;
+        if record.exists() {
+            sent = _load_partial(http, &record, &head).await?;
+            files.push(record);
+        }
+        messages.extend(sent.iter().map(|(_, msg)| msg.clone()));
+        let (primary, entry) = _upload_parts(
+            http,
+            head.clone(),
+            &part_paths,
+            &parity_paths,
+            destination,
+            &mut messages,
+            &mut threads,
+            &mut sent,
+            &callback,
+        )
+        .await?;
 
-    let mut progress = 0;
-    let total = messages.len();
-    for (i, message) in messages.iter().enumerate() {
-        let mut content = String::new();
-        let next = messages.get(i + 1).cloned();
-        if i == 0 {
-            content = format!("{msg}\nlen={}\n", part_paths.len());
+        let mut copies = vec![(primary[0].clone(), entry)];
+        for replica in replicas {
+            info!("{} to channel {replica}", "Replicating".blue().bold());
+            let (copy, entry) = _upload_parts(
+                http,
+                head.clone(),
+                &part_paths,
+                &parity_paths,
+                Destination::new(std::slice::from_ref(replica), *thread)?,
+                &mut messages,
+                &mut threads,
+                &mut Vec::new(),
+                &callback,
+            )
+            .await?;
+            copies.push((copy[0].clone(), entry));
         }
-        if let Some(v) = next {
-            content += &format!("next={}", v.id);
+        if copies.len() > 1 {
+            _link_replicas(http, copies, &mut messages).await?;
         }
-        message
-            .clone()
-            .edit(&http, EditMessage::new().content(content))
-            .await?;
-        progress += 1;
-
-        let fraction = if total > 0 {
-            progress as f64 / total as f64
-        } else {
-            1.0
-        };
 
-        callback("Editing".to_string(), fraction);
+        Ok(primary)
     }
+    .await;
+
+    let primary = match res {
+        Ok(v) => v,
+        Err(e) => {
+            match (*keep_partial, partial) {
+                (true, Some(head)) => {
+                    _keep_partial(http, &cache_dir, &head, sent, messages, &threads).await
+                }
+                (true, None) => warn!("Keeping the parts in {}", cache_dir.display()),
+                (false, _) => _rollback(http, messages, &threads, &files).await,
+            }
+            return Err(e);
+        }
+    };
 
     info!("Cleaning up...");
 
-    for part in part_paths {
+    for part in files {
         info!("{} {}", "Removing".blue().bold(), part.display());
-        fs::remove_file(part).context("Failed to remove file")?;
+        fs::remove_file(part)?;
     }
 
-    Ok(messages)
+    Ok(primary)
+}
+
+/// Deflates `path` into a directory of `cache_dir`, under the same name so
+/// the parts are still named after it. Returns the compressed file.
+fn _compress(path: &Path, cache_dir: &Path) -> Result<PathBuf> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| DistoreError::InvalidFileName(path.to_path_buf()))?;
+    let mut source = File::open(path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("Cannot open file {}: {e}", path.display()),
+        )
+    })?;
+    let dir = cache_dir.join(DEFLATE);
+    fs::create_dir_all(&dir)?;
+    let out = dir.join(name);
+
+    info!("{} {}", "Compressing".blue().bold(), path.display());
+    let mut encoder = DeflateEncoder::new(File::create(&out)?, Compression::default());
+    let res = io::copy(&mut source, &mut encoder).and_then(|_| encoder.finish());
+    if let Err(e) = res {
+        _remove_parts(&[out]);
+        return Err(e.into());
+    }
+    Ok(out)
+}
+
+/// Inflates the compressed file at `from` into `to`, removing `from`.
+fn _inflate_file(from: &Path, to: &Path) -> Result<()> {
+    info!("{} {}", "Decompressing".blue().bold(), to.display());
+    let mut decoder = DeflateDecoder::new(File::create(to)?);
+    io::copy(&mut File::open(from)?, &mut decoder)?;
+    decoder.finish()?;
+    fs::remove_file(from)?;
+    Ok(())
+}
+
+/// Inflates the next `chunk` of a compressed entry, or what's left once
+/// there are no more chunks. Returns the bytes inflated so far.
+pub(crate) fn _inflate(
+    decoder: &mut DeflateDecoder<Vec<u8>>,
+    chunk: Option<&[u8]>,
+) -> Result<Vec<u8>> {
+    match chunk {
+        Some(chunk) => decoder.write_all(chunk)?,
+        None => decoder.try_finish()?,
+    }
+    Ok(std::mem::take(decoder.get_mut()))
+}
+
+/// Returns `custom`, or the distore directory in the user's cache directory,
+/// making sure it exists.
+fn _cache_dir(custom: Option<&Path>) -> Result<PathBuf> {
+    let dir = match custom {
+        Some(dir) => dir.to_path_buf(),
+        None => dirs::cache_dir()
+            .ok_or(DistoreError::NoCacheDir)?
+            .join("distore"),
+    };
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// Checks that `name` can be given to a file: a single path component that
+/// isn't empty, `.` or `..`.
+pub(crate) fn check_name(name: &str) -> Result<()> {
+    if name.is_empty() {
+        return Err(DistoreError::EmptyName);
+    }
+    if name.contains(['/', '\\']) || name == "." || name == ".." {
+        return Err(DistoreError::UnsafeName(name.into()));
+    }
+    Ok(())
+}
+
+/// Reduces a name read from a manifest to its last path component, so that
+/// joining it to a directory can't point outside of it. Every name read from
+/// a manifest goes through this before it becomes a path, as parsing doesn't
+/// check it.
+pub(crate) fn _file_name(name: &str) -> Result<PathBuf> {
+    let last = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    check_name(last).map_err(|_| DistoreError::UnsafeName(name.into()))?;
+    Ok(PathBuf::from(last))
+}
+
+/// Where the parts kept by a failed upload of `head` are recorded.
+fn _partial_path(cache_dir: &Path, head: &FileEntry) -> Result<PathBuf> {
+    let name = _file_name(head.name.as_deref().unwrap_or_default())?;
+    let mut name = name.into_os_string();
+    name.push(".partial");
+    Ok(cache_dir.join(name))
+}
+
+/// Records the data messages of the first copy sent by a failed upload, with
+/// the offset of their first part, so that the next upload of the file
+/// continues from them. Every other message is deleted, as the next upload
+/// sends it again, and so is every thread that holds none of the kept ones.
+async fn _keep_partial(
+    http: &HttpPool,
+    cache_dir: &Path,
+    head: &FileEntry,
+    sent: Vec<(usize, Message)>,
+    messages: Vec<Message>,
+    threads: &[u64],
+) {
+    let data_chunks = head.len.unwrap_or_default().div_ceil(PARTS_PER_MESSAGE);
+    let chunk_size = head.part_size.unwrap_or(PART_SIZE as u64) * PARTS_PER_MESSAGE as u64;
+    let record = FileEntry {
+        parts: sent
+            .iter()
+            .filter(|(i, _)| *i < data_chunks)
+            .map(|(i, msg)| PartRef {
+                id: msg.id.get(),
+                offset: *i as u64 * chunk_size,
+                channel: Some(msg.channel_id.get()),
+            })
+            .collect(),
+        ..head.clone()
+    };
+
+    let res = _partial_path(cache_dir, head).and_then(|path| {
+        fs::write(&path, record.to_string())?;
+        Ok(path)
+    });
+    let path = match res {
+        Ok(path) => path,
+        Err(e) => {
+            warn!("Failed to record the sent parts: {e}");
+            return _rollback(http, messages, threads, &[]).await;
+        }
+    };
+    let kept: HashSet<u64> = record.parts.iter().map(|p| p.id).collect();
+    // A thread of a text channel has the ID of its starter message
+    let mut kept_threads = kept.clone();
+    kept_threads.extend(record.parts.iter().filter_map(|p| p.channel));
+    let dropped: Vec<Message> = messages
+        .into_iter()
+        .filter(|m| !kept.contains(&m.id.get()))
+        .collect();
+    let threads: Vec<u64> = threads
+        .iter()
+        .copied()
+        .filter(|t| !kept_threads.contains(t))
+        .collect();
+    _rollback(http, dropped, &threads, &[]).await;
+    warn!(
+        "Keeping {} sent message(s), recorded in {}. Upload the file again to continue",
+        kept.len(),
+        path.display()
+    );
+}
+
+/// Reads the messages a failed upload of `head` recorded at `path`, by the
+/// chunk they hold. Records of another version of the file are ignored, and
+/// chunks whose message is gone are sent again.
+async fn _load_partial(
+    http: &HttpPool,
+    path: &Path,
+    head: &FileEntry,
+) -> Result<Vec<(usize, Message)>> {
+    let record = FileEntry::from_str(&fs::read_to_string(path)?)?;
+    if (
+        &record.sha256,
+        record.part_size,
+        &record.compression,
+        record.len,
+    ) != (&head.sha256, head.part_size, &head.compression, head.len)
+    {
+        warn!("Ignoring {}, the file has changed", path.display());
+        return Ok(Vec::new());
+    }
+
+    let chunk_size = head.part_size.unwrap_or(PART_SIZE as u64) * PARTS_PER_MESSAGE as u64;
+    let mut sent = Vec::new();
+    for part in record.parts {
+        let channel = part
+            .channel
+            .ok_or(DistoreError::IncompleteManifest("channel"))?;
+        match http.get_message(channel.into(), part.id.into()).await {
+            Ok(msg) => sent.push(((part.offset / chunk_size) as usize, msg)),
+            Err(e) => warn!("Sending the parts of message {} again: {e}", part.id),
+        }
+    }
+    info!(
+        "{} from {} message(s) already sent",
+        "Resuming".blue().bold(),
+        sent.len()
+    );
+    Ok(sent)
+}
+
+/// Deletes the messages, threads and part files of a failed upload, logging
+/// anything that couldn't be removed. Threads are deleted last, as deleting a
+/// forum post takes its messages with it.
+async fn _rollback(
+    http: &HttpPool,
+    messages: Vec<Message>,
+    threads: &[u64],
+    part_paths: &[PathBuf],
+) {
+    warn!("Upload failed, rolling back...");
+    for msg in messages {
+        if let Err(e) = msg.delete(http.client_for(&msg)).await {
+            warn!("Failed to delete message {}: {e}", msg.id);
+        }
+    }
+    for thread in threads {
+        if let Err(e) = http.delete_channel((*thread).into(), None).await {
+            warn!("Failed to delete thread {thread}: {e}");
+        }
+    }
+    _remove_parts(part_paths);
+}
+
+/// Removes the part files in `paths` that exist, warning about the ones that
+/// can't be removed.
+fn _remove_parts(paths: &[PathBuf]) {
+    for part in paths.iter().filter(|p| p.exists()) {
+        if let Err(e) = fs::remove_file(part) {
+            warn!("Failed to remove {}: {e}", part.display());
+        }
+    }
+}
+
+/// Records the location of every copy of an entry in each copy's head message.
+/// Index messages sent on the way are pushed to `messages`.
+async fn _link_replicas(
+    http: &HttpPool,
+    copies: Vec<(Message, FileEntry)>,
+    messages: &mut Vec<Message>,
+) -> Result<()> {
+    let locations: Vec<MessageRef> = copies
+        .iter()
+        .map(|(msg, _)| MessageRef {
+            id: msg.id.get(),
+            channel: msg.channel_id.get(),
+        })
+        .collect();
+
+    for (mut msg, mut entry) in copies {
+        entry.replicas = locations
+            .iter()
+            .copied()
+            .filter(|r| r.id != msg.id.get())
+            .collect();
+        messages.extend(_write_head(http, &mut msg, &mut entry).await?);
+    }
+    Ok(())
+}
+
+/// Writes the manifest of an entry to its head message. When the part index
+/// makes the manifest too long, it is attached to a message of its own and
+/// the manifest points to it instead. Returns the index message if a new one
+/// was sent, so that it can be rolled back with the rest of an upload.
+async fn _write_head(
+    http: &HttpPool,
+    head: &mut Message,
+    entry: &mut FileEntry,
+) -> Result<Option<Message>> {
+    let mut old_index = None;
+    let mut new_index = None;
+    if !entry.parts.is_empty() {
+        old_index = entry.index.take();
+        if entry.to_string().len() > MESSAGE_LIMIT {
+            let index = FileEntry {
+                parts: std::mem::take(&mut entry.parts),
+                ..Default::default()
+            };
+            let name = format!("{}.index", entry.name.as_deref().unwrap_or_default());
+            let attachment = CreateAttachment::bytes(index.to_string(), name);
+            let msg =
+                _send_attachments(http, head.channel_id.get(), vec![attachment], String::new())
+                    .await?;
+            entry.index = Some(msg.id.get());
+            entry.parts = index.parts;
+            new_index = Some(msg);
+        }
+    }
+
+    let mut content = entry.clone();
+    if content.index.is_some() {
+        content.parts.clear();
+    }
+    let content = content.to_string();
+    let res = match content.len() > MESSAGE_LIMIT {
+        true => Err(DistoreError::ManifestTooLong),
+        false => head
+            .edit(http.client_for(head), EditMessage::new().content(content))
+            .await
+            .map_err(DistoreError::from),
+    };
+    if let Err(e) = res {
+        // The head doesn't point to the new index, so nothing else would
+        if let Some(index) = new_index {
+            if let Err(e) = index.delete(http.client_for(&index)).await {
+                warn!("Failed to delete the part index {}: {e}", index.id);
+            }
+        }
+        return Err(e);
+    }
+
+    if let Some(old) = old_index.filter(|old| entry.index != Some(*old)) {
+        if let Err(e) = http.delete_message(head.channel_id, old.into(), None).await {
+            warn!("Failed to delete the old part index {old}: {e}");
+        }
+    }
+    Ok(new_index)
+}
+
+/// Reads the part index of `entry` from its index message, if it has one.
+/// Returns the index message.
+pub(crate) async fn _load_index(
+    http: &HttpPool,
+    head: &Message,
+    entry: &mut FileEntry,
+) -> Result<Option<Message>> {
+    let Some(index) = entry.index else {
+        return Ok(None);
+    };
+    let msg = http.get_message(head.channel_id, index.into()).await?;
+    let attachment = msg
+        .attachments
+        .first()
+        .ok_or(DistoreError::IncompleteManifest("index"))?;
+    let bytes = _download(http.client(), attachment).await?;
+    let content = String::from_utf8(bytes)
+        .map_err(|_| DistoreError::Integrity(format!("{} is corrupt", attachment.filename)))?;
+    entry.parts = FileEntry::from_str(&content)?.parts;
+    Ok(Some(msg))
+}
+
+/// Computes `parity` parity parts for every group of [`PARITY_GROUP`] parts and
+/// writes them next to the parts as `<name>.parity<N>`. Nothing is left behind
+/// if it fails.
+fn _write_parity_parts(
+    part_paths: &[PathBuf],
+    parity: usize,
+    output: &Path,
+    filename: &str,
+) -> Result<Vec<PathBuf>> {
+    let mut out = Vec::new();
+    if let Err(e) = _write_parity_groups(part_paths, parity, output, filename, &mut out) {
+        _remove_parts(&out);
+        return Err(e);
+    }
+    Ok(out)
+}
+
+fn _write_parity_groups(
+    part_paths: &[PathBuf],
+    parity: usize,
+    output: &Path,
+    filename: &str,
+    out: &mut Vec<PathBuf>,
+) -> Result<()> {
+    for group in part_paths.chunks(PARITY_GROUP) {
+        let codec = ReedSolomon::new(group.len(), parity)?;
+        let shard_len = group[0].metadata()?.len();
+        let mut inputs = group
+            .iter()
+            .map(File::open)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut outputs = Vec::new();
+        for _ in 0..parity {
+            let name = format!("{}.parity{}", filename, out.len());
+            info!("{} {name}", "Writing".blue().bold());
+            out.push(output.join(name));
+            outputs.push(File::create(out.last().unwrap())?);
+        }
+
+        let mut written = 0;
+        while written < shard_len {
+            let block = PARITY_BLOCK.min((shard_len - written) as usize);
+            let mut shards = Vec::new();
+            for input in inputs.iter_mut() {
+                let mut buf = Vec::with_capacity(block);
+                input.take(block as u64).read_to_end(&mut buf)?;
+                buf.resize(block, 0);
+                shards.push(buf);
+            }
+
+            let shards: Vec<&[u8]> = shards.iter().map(Vec::as_slice).collect();
+            for (file, buf) in outputs.iter_mut().zip(codec.encode(&shards)?) {
+                file.write_all(&buf)?;
+            }
+            written += block as u64;
+        }
+    }
+    Ok(())
+}
+
+/// Short hash of a single part, used to tell corrupt parts apart.
+fn part_hash(bytes: &[u8]) -> String {
+    hex(&digest::digest(&digest::SHA256, bytes).as_ref()[..8])
+}
+
+/// Like [`part_hash`], reading the part at `path` a block at a time so that
+/// it isn't held in memory whole.
+fn part_hash_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hash = digest::Context::new(&digest::SHA256);
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hash.update(&buf[..read]);
+    }
+    Ok(hex(&hash.finish().as_ref()[..8]))
+}
+
+/// Sends the parts and links them together. Every message sent is pushed to
+/// `messages` as soon as it exists so a failed upload can be rolled back, and
+/// to `sent` with the index of the chunk it holds. The thread it's sent to,
+/// if any, is pushed to `threads`. Chunks already in `sent`
+/// aren't sent again. Parity parts are sent in messages of their own, listed
+/// in the head. Returns the messages of this copy in order, with the manifest
+/// of its head.
+#[allow(clippy::too_many_arguments)]
+async fn _upload_parts<F: Fn(String, f64)>(
+    http: &HttpPool,
+    head: FileEntry,
+    part_paths: &[PathBuf],
+    parity_paths: &[PathBuf],
+    destination: Destination<'_>,
+    messages: &mut Vec<Message>,
+    threads: &mut Vec<u64>,
+    sent: &mut Vec<(usize, Message)>,
+    callback: F,
+) -> Result<(Vec<Message>, FileEntry)> {
+    let filename = head.name.clone().unwrap_or_default();
+
+    info!("Uploading...");
+    let data_chunks = part_paths.len().div_ceil(PARTS_PER_MESSAGE);
+    let chunks: Vec<Vec<PathBuf>> = part_paths
+        .chunks(PARTS_PER_MESSAGE)
+        .chain(parity_paths.chunks(PARTS_PER_MESSAGE))
+        .map(|chunk| chunk.to_vec())
+        .collect();
+    let hashes = chunks
+        .iter()
+        .map(|chunk| {
+            chunk
+                .iter()
+                .map(|path| part_hash_file(path))
+                .collect::<Result<Vec<_>>>()
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    callback(format!("Uploading {}", filename), 0.0);
+    let channels = match destination {
+        Destination::Channels([]) => return Err(ConfigError::NoChannel.into()),
+        Destination::Channels(channels) => channels.to_vec(),
+        Destination::Thread(channel) => {
+            let thread = match sent.iter().find(|(i, _)| *i == 0) {
+                // Threads in text channels have the ID of their starter
+                // message, and forum posts hold theirs
+                Some((_, msg)) if msg.channel_id.get() == channel => msg.id.get(),
+                Some((_, msg)) => msg.channel_id.get(),
+                None => {
+                    let first = chunks.first().cloned().unwrap_or_default();
+                    let (msg, thread) = _start_thread(http, channel, &filename, first).await?;
+                    messages.push(msg.clone());
+                    sent.push((0, msg));
+                    thread
+                }
+            };
+            threads.push(thread);
+            vec![thread]
+        }
+    };
+    let done: HashSet<usize> = sent.iter().map(|(i, _)| *i).collect();
+    info!(
+        "Sending {} message(s) to {} channel(s) in total",
+        chunks.len(),
+        channels.len()
+    );
+
+    // Stop sending new batches once one of them fails
+    let failed = AtomicBool::new(false);
+    let chunks_len = chunks.len();
+    let remaining = chunks
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| !done.contains(i));
+    let mut sends = stream::iter(remaining)
+        .map(|(i, chunk)| {
+            let failed = &failed;
+            let channel = channels[i % channels.len()];
+            async move {
+                if failed.load(Ordering::SeqCst) {
+                    return (i, Err(DistoreError::Cancelled));
+                }
+                let client = http
+                    .pick(Route::ChannelMessages {
+                        channel_id: channel.into(),
+                    })
+                    .await;
+                let res = _send_chunk(&client, channel, chunk).await;
+                if res.is_err() {
+                    failed.store(true, Ordering::SeqCst);
+                }
+                (i, res)
+            }
+        })
+        .buffer_unordered(http.concurrency());
+
+    let mut error = None;
+    let total = chunks_len;
+    while let Some((i, res)) = sends.next().await {
+        let msg = match res {
+            Ok(v) => v,
+            Err(e) => {
+                error.get_or_insert(e);
+                continue;
+            }
+        };
+        messages.push(msg.clone());
+
+        let fraction = if total > 0 {
+            (sent.len() + 1) as f64 / total as f64
+        } else {
+            1.0
+        };
+
+        let fraction = fraction.clamp(0.0, 1.0);
+        callback(format!("Uploading {}", filename), fraction);
+
+        let suffix = |a: Option<&Attachment>| {
+            a.and_then(|a| a.filename.rsplit('.').next())
+                .unwrap_or_default()
+                .to_string()
+        };
+        info!(
+            "Sent {}..{}",
+            suffix(msg.attachments.first()),
+            suffix(msg.attachments.last())
+        );
+        sent.push((i, msg));
+    }
+    drop(sends);
+
+    if let Some(e) = error {
+        return Err(e);
+    }
+    sent.sort_by_key(|(i, _)| *i);
+    let mut sent: Vec<Message> = sent.iter().map(|(_, msg)| msg.clone()).collect();
+    let parity_sent = sent.split_off(data_chunks);
+
+    _link_parts(http, head, sent, parity_sent, hashes, messages, callback).await
+}
+
+/// Writes the manifest of an entry to its head message and links the data
+/// messages together. `hashes` holds the part hashes of every data message
+/// followed by those of every parity message. The part index, if one is sent,
+/// is pushed to `messages`.
+async fn _link_parts<F: Fn(String, f64)>(
+    http: &HttpPool,
+    mut head: FileEntry,
+    mut sent: Vec<Message>,
+    parity_sent: Vec<Message>,
+    hashes: Vec<Vec<String>>,
+    messages: &mut Vec<Message>,
+    callback: F,
+) -> Result<(Vec<Message>, FileEntry)> {
+    let home = sent[0].channel_id.get();
+    let data_chunks = sent.len();
+
+    info!("Editing messages...");
+
+    let mut offset = 0;
+    for message in sent.iter() {
+        head.parts.push(PartRef {
+            id: message.id.into(),
+            offset,
+            channel: Some(message.channel_id.get()).filter(|c| *c != home),
+        });
+        offset += message
+            .attachments
+            .iter()
+            .map(|a| a.size as u64)
+            .sum::<u64>();
+    }
+    head.parity_messages = parity_sent
+        .iter()
+        .map(|msg| MessageRef {
+            id: msg.id.get(),
+            channel: msg.channel_id.get(),
+        })
+        .collect();
+    head.hashes = hashes[0].clone();
+
+    let mut progress = 0;
+    let total = sent.len() + parity_sent.len();
+    let mut head_message = None;
+    for (i, message) in sent.iter().enumerate() {
+        let mut entry = match i {
+            0 => head.clone(),
+            _ => FileEntry {
+                hashes: hashes[i].clone(),
+                ..Default::default()
+            },
+        };
+        let next = sent.get(i + 1);
+        entry.next = next.map(|v| v.id.into());
+        entry.next_channel = next
+            .map(|v| v.channel_id)
+            .filter(|c| *c != message.channel_id)
+            .map(|c| c.get());
+        match i {
+            0 => {
+                let mut message = message.clone();
+                messages.extend(_write_head(http, &mut message, &mut entry).await?);
+                head = entry;
+                head_message = Some(message);
+            }
+            _ => {
+                message
+                    .clone()
+                    .edit(
+                        http.client_for(message),
+                        EditMessage::new().content(entry.to_string()),
+                    )
+                    .await?;
+            }
+        }
+        progress += 1;
+
+        let fraction = if total > 0 {
+            progress as f64 / total as f64
+        } else {
+            1.0
+        };
+
+        callback("Editing".to_string(), fraction);
+    }
+
+    for (i, message) in parity_sent.iter().enumerate() {
+        let entry = FileEntry {
+            hashes: hashes[data_chunks + i].clone(),
+            ..Default::default()
+        };
+        message
+            .clone()
+            .edit(
+                http.client_for(message),
+                EditMessage::new().content(entry.to_string()),
+            )
+            .await?;
+        progress += 1;
+        callback("Editing".to_string(), progress as f64 / total as f64);
+    }
+
+    // Hand back the head with the manifest it was just edited to hold
+    if let Some(message) = head_message {
+        sent[0] = message;
+    }
+    Ok((sent, head))
+}
+
+/// Sends the first message of an upload and starts a thread named after the
+/// file from it. Forum channels get a new post instead, with the message as
+/// its starter message. Returns the message and the ID of the thread.
+async fn _start_thread(
+    http: &HttpPool,
+    channel: u64,
+    name: &str,
+    chunk: Vec<PathBuf>,
+) -> Result<(Message, u64)> {
+    // Thread names are limited to 100 characters
+    let title: String = name.chars().take(100).collect();
+
+    let forum = match http.get_channel(channel.into()).await? {
+        Channel::Guild(c) => c.kind == ChannelType::Forum,
+        _ => false,
+    };
+    if !forum {
+        let msg = _send_chunk(http, channel, chunk).await?;
+        let res = ChannelId::from(channel)
+            .create_thread_from_message(&**http, msg.id, CreateThread::new(title))
+            .await;
+        if let Err(e) = res {
+            if let Err(e) = msg.delete(http.client_for(&msg)).await {
+                warn!("Failed to delete message {}: {e}", msg.id);
+            }
+            return Err(e.into());
+        }
+        let thread = msg.id.get();
+        return Ok((msg, thread));
+    }
+
+    let attachments = join_all(chunk.into_iter().map(CreateAttachment::path))
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    let post = ChannelId::from(channel)
+        .create_forum_post(
+            &**http,
+            CreateForumPost::new(
+                title,
+                CreateMessage::new().content("tmp").files(attachments),
+            ),
+        )
+        .await?;
+
+    // The starter message of a post shares its ID
+    let msg = http.get_message(post.id, post.id.get().into()).await?;
+    Ok((msg, post.id.get()))
+}
+
+async fn _send_chunk(http: &Http, channel: u64, chunk: Vec<PathBuf>) -> Result<Message> {
+    let attachment_futures: Vec<_> = chunk.into_iter().map(CreateAttachment::path).collect();
+    let attachments = join_all(attachment_futures)
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let msg = ChannelId::from(channel)
+        .send_files(&http, attachments, CreateMessage::new().content("tmp"))
+        .await?;
+    Ok(msg)
 }
 
 pub async fn download(
     message_id: u64,
     token: Option<String>,
     channel: Option<u64>,
+    remote: Option<String>,
     dir: Option<PathBuf>,
     output: Option<PathBuf>,
 ) -> Result<()> {
-    let mut path = dir
-        .unwrap_or(dirs::config_dir().ok_or(ConfigError::NoConfigDir)?)
-        .join("distore");
-    fs::create_dir_all(&path).context("Failed to create config directory")?;
-    path.push("distore.ini");
-
-    let token = token.unwrap_or_else(|| {
-        crate::config::ConfigValue::get_current_config(&path)
-            .context("Failed to get the config file")
-            .unwrap()
-            .0
-            .inner()
-            .to_string()
-    });
-    let channel = channel.unwrap_or_else(|| {
-        crate::config::ConfigValue::get_current_config(&path)
-            .unwrap()
-            .1
-            .inner()
-            .parse()
-            .unwrap()
-    });
-
-    let http = Http::new(&token);
+    let (http, channels, settings) = _connect(token, channel, remote.as_deref(), dir)?;
+    let channel = channels[0];
 
     let (_, _, name, len) = _get_download_variables(&http, message_id, channel).await?;
+    let output = match settings.output_dir {
+        Some(d) if output.is_none() => Some(d.join(_file_name(&name)?)),
+        _ => output,
+    };
 
     let multi = MultiProgress::new();
     let logger = colog::default_builder()
         .filter(Some("serenity"), log::LevelFilter::Off)
         .build();
-    LogWrapper::new(multi.clone(), logger)
-        .try_init()
-        .context("Failed to initilize logger")
-        .unwrap();
+    // A logger may already be set by whoever calls this
+    _ = LogWrapper::new(multi.clone(), logger).try_init();
     let pb = multi.add(ProgressBar::new(len as u64));
 
     pb.set_style(
@@ -438,268 +1721,2032 @@ pub async fn download(
     pb.set_message("Assembling");
 
     let pb_clone = pb.clone();
-    download_internal(&http, message_id, channel, output.clone(), move |_| {
-        pb_clone.inc(1)
-    })
-    .await?;
+    let path =
+        download_internal(&http, message_id, channel, output, move |_| pb_clone.inc(1)).await?;
 
     pb.finish();
 
-    println!(
-        "{} {}",
-        "Downloaded".green().bold(),
-        output.unwrap_or(name.into()).display()
-    );
+    println!("{} {}", "Downloaded".green().bold(), path.display());
 
     Ok(())
 }
 
-pub(crate) async fn _get_download_variables(
+/// Fetches the head message of an entry. Uploads to a forum channel are posts
+/// of their own, and their starter message shares the ID of the post.
+pub(crate) async fn _get_head(
     http: &Http,
+    channel: u64,
+    message_id: u64,
+) -> Result<Message, serenity::Error> {
+    match http.get_message(channel.into(), message_id.into()).await {
+        Ok(msg) => Ok(msg),
+        Err(e) => http
+            .get_message(message_id.into(), message_id.into())
+            .await
+            .map_err(|_| e),
+    }
+}
+
+pub(crate) async fn _get_download_variables(
+    http: &HttpPool,
     message_id: u64,
     channel: u64,
 ) -> Result<(Message, FileEntry, String, usize)> {
-    let msg = http.get_message(channel.into(), message_id.into()).await?;
-    let entry = FileEntry::from_str(&msg.content)?;
-    let name = entry.name.clone().ok_or(anyhow!("Invalid Message"))?;
-    let len = entry.len.ok_or(anyhow!("Invalid Message"))?;
+    let msg = _get_head(http, channel, message_id).await?;
+    let mut entry = FileEntry::from_str(&msg.content)?;
+    _load_index(http, &msg, &mut entry).await?;
+    let name = entry
+        .name
+        .clone()
+        .ok_or(DistoreError::IncompleteManifest("name"))?;
+    let len = entry.len.ok_or(DistoreError::IncompleteManifest("len"))?;
+
+    Ok((msg, entry, name, len))
+}
+
+/// Downloads an entry, falling back to its replicas in order when a copy
+/// can't be read. Compressed entries are inflated. Without an `output`, it's
+/// written to the current directory, named after the last component of its
+/// name.
+pub(crate) async fn download_internal<F: Fn(f64)>(
+    http: &HttpPool,
+    message_id: u64,
+    channel: u64,
+    output: Option<PathBuf>,
+    callback: F,
+) -> Result<PathBuf> {
+    let (msg, entry, name, _) = _get_download_variables(http, message_id, channel).await?;
+    let path = match output {
+        Some(output) => output,
+        None => _file_name(&name)?,
+    };
+
+    match entry.compression.as_deref() {
+        None => _download_stored(http, msg, &entry, &path, &callback).await?,
+        Some(DEFLATE) => {
+            let mut stored = path.clone().into_os_string();
+            stored.push(".deflate");
+            let stored = PathBuf::from(stored);
+            _download_stored(http, msg, &entry, &stored, &callback).await?;
+            _inflate_file(&stored, &path)?;
+        }
+        Some(other) => return Err(DistoreError::UnsupportedCompression(other.to_string())),
+    }
+
+    Ok(path)
+}
+
+/// Downloads an entry as it is stored, without inflating it, falling back to
+/// its replicas in order when a copy can't be read.
+async fn _download_stored<F: Fn(f64)>(
+    http: &HttpPool,
+    msg: Message,
+    entry: &FileEntry,
+    path: &Path,
+    callback: &F,
+) -> Result<()> {
+    let mut res = _download_copy(http, msg, entry, path, callback).await;
+    for replica in entry.replicas.iter() {
+        let Err(e) = &res else {
+            break;
+        };
+        warn!("{e}, trying the copy in channel {}", replica.channel);
+
+        res = match _get_download_variables(http, replica.id, replica.channel).await {
+            Ok((msg, entry, _, _)) => _download_copy(http, msg, &entry, path, callback).await,
+            Err(e) => Err(e),
+        };
+    }
+
+    res
+}
+
+async fn _download_copy<F: Fn(f64)>(
+    http: &HttpPool,
+    msg: Message,
+    entry: &FileEntry,
+    path: &Path,
+    callback: &F,
+) -> Result<()> {
+    if entry.parity.is_some_and(|p| p > 0) {
+        return _download_with_parity(http, msg, entry, path, callback).await;
+    }
+
+    let size = entry.size.ok_or(DistoreError::IncompleteManifest("size"))?;
+    let len = entry.len.ok_or(DistoreError::IncompleteManifest("len"))?;
+
+    let messages = _get_part_messages(http, msg, entry, 0..usize::MAX).await?;
+    let slots = _part_slots(messages.iter(), ".part", len);
+    if let Some(i) = slots.iter().position(Option::is_none) {
+        return Err(DistoreError::Integrity(format!("Part {i} is missing")));
+    }
+
+    let mut out = File::create(path)?;
+
+    let mut parts = stream::iter(slots.into_iter().flatten())
+        .map(|(part, hash)| async move {
+            info!("{} {}", "Downloading".blue().bold(), part.filename);
+            let bytes = _download(http.client(), &part).await?;
+            if hash.is_some_and(|h| h != part_hash(&bytes)) {
+                return Err(DistoreError::Integrity(format!(
+                    "{} is corrupt",
+                    part.filename
+                )));
+            }
+            Ok(bytes)
+        })
+        .buffered(http.concurrency());
+
+    let mut progress = 0;
+    while let Some(part) = parts.next().await {
+        let part = part?;
+
+        progress += part.len();
+        let fraction = if size > 0 {
+            progress as f64 / size as f64
+        } else {
+            1.0
+        };
+
+        let fraction = fraction.clamp(0.0, 1.0);
+
+        out.write_all(&part)?;
+        callback(fraction);
+    }
+
+    Ok(())
+}
+
+/// A part of an entry with the hash recorded for it, if it could be found.
+pub(crate) type Slot = Option<(Attachment, Option<String>)>;
+
+/// Downloads an entry group by group, rebuilding parts that are missing or
+/// corrupt from the parity parts of their group.
+async fn _download_with_parity<F: Fn(f64)>(
+    http: &HttpPool,
+    msg: Message,
+    entry: &FileEntry,
+    path: &Path,
+    callback: &F,
+) -> Result<()> {
+    let size = entry.size.ok_or(DistoreError::IncompleteManifest("size"))?;
+    let len = entry.len.ok_or(DistoreError::IncompleteManifest("len"))?;
+    let parity = entry.parity.unwrap_or_default();
+    let group = entry.parity_group.unwrap_or(PARITY_GROUP);
+
+    let (data, parity_messages) = _get_surviving_messages(http, msg, entry).await?;
+    let data_slots = _part_slots(data.iter().flatten(), ".part", len);
+    let parity_slots = _part_slots(
+        parity_messages.iter().flatten(),
+        ".parity",
+        len.div_ceil(group) * parity,
+    );
+
+    let mut out = File::create(path)?;
+    let mut progress = 0;
+    for (g, data) in data_slots.chunks(group).enumerate() {
+        let lens: Vec<usize> = (g * group..g * group + data.len())
+            .map(|i| _part_len(entry, i))
+            .collect();
+        let parity = &parity_slots[g * parity..(g + 1) * parity];
+
+        let (shards, rebuilt) = _read_group(http, data, parity, &lens, false).await?;
+        let rebuilt = rebuilt.iter().filter(|i| **i < data.len()).count();
+        if rebuilt > 0 {
+            warn!("Rebuilt {rebuilt} part(s) from parity, run `distore repair` to fix them");
+        }
+
+        for shard in shards.iter().take(data.len()) {
+            out.write_all(shard)?;
+            progress += shard.len();
+            let fraction = if size > 0 {
+                progress as f64 / size as f64
+            } else {
+                1.0
+            };
+            callback(fraction.clamp(0.0, 1.0));
+        }
+    }
+
+    Ok(())
+}
+
+/// Fetches the data and parity messages of an entry, with `None` for the ones
+/// that are gone. Entries without a part index can only follow the message
+/// chain, so a missing data message is an error for them.
+async fn _get_surviving_messages(
+    http: &HttpPool,
+    head: Message,
+    entry: &FileEntry,
+) -> Result<(Vec<Option<Message>>, Vec<Option<Message>>)> {
+    let data = match entry.parts.is_empty() {
+        true => _get_part_messages(http, head, entry, 0..usize::MAX)
+            .await?
+            .into_iter()
+            .map(Some)
+            .collect(),
+        false => {
+            let head = &head;
+            stream::iter(entry.parts.iter().copied())
+                .map(|part| async move {
+                    if part.id == head.id.get() {
+                        return Some(head.clone());
+                    }
+                    let channel = part.channel.unwrap_or(head.channel_id.get());
+                    _get_message(http, channel, part.id)
+                        .await
+                        .inspect_err(|e| warn!("Message {} is gone: {e}", part.id))
+                        .ok()
+                })
+                .buffered(http.concurrency())
+                .collect()
+                .await
+        }
+    };
+
+    let parity = stream::iter(entry.parity_messages.iter().copied())
+        .map(|msg| async move {
+            _get_message(http, msg.channel, msg.id)
+                .await
+                .inspect_err(|e| warn!("Message {} is gone: {e}", msg.id))
+                .ok()
+        })
+        .buffered(http.concurrency())
+        .collect()
+        .await;
+
+    Ok((data, parity))
+}
+
+/// Places the attachments of `messages` at their position in the entry, read
+/// from the `<suffix><N>` ending of their file names.
+fn _part_slots<'a>(
+    messages: impl Iterator<Item = &'a Message>,
+    suffix: &str,
+    len: usize,
+) -> Vec<Slot> {
+    let mut slots = vec![None; len];
+    for msg in messages {
+        let hashes = FileEntry::from_str(&msg.content)
+            .map(|e| e.hashes)
+            .unwrap_or_default();
+
+        for attachment in msg.attachments.iter() {
+            let Some(n) = attachment
+                .filename
+                .rsplit_once(suffix)
+                .and_then(|(_, n)| n.parse::<usize>().ok())
+            else {
+                continue;
+            };
+            if let Some(slot) = slots.get_mut(n) {
+                let hash = hashes.get(n % PARTS_PER_MESSAGE).cloned();
+                *slot = Some((attachment.clone(), hash));
+            }
+        }
+    }
+    slots
+}
+
+/// Size of the `i`th part of an entry.
+fn _part_len(entry: &FileEntry, i: usize) -> usize {
+    let size = entry.size.unwrap_or_default();
+    let part_size = entry.part_size.unwrap_or(PART_SIZE as u64);
+    size.saturating_sub(i as u64 * part_size).min(part_size) as usize
+}
+
+/// Downloads an attachment through the client of the pool, so it goes
+/// through the configured proxy.
+async fn _download(client: &Client, attachment: &Attachment) -> Result<Vec<u8>> {
+    let res = client.get(&attachment.url).send().await?;
+    Ok(res.error_for_status()?.bytes().await?.to_vec())
+}
+
+/// Downloads a part. Returns `None` if it is gone or doesn't match its hash.
+async fn _fetch_part(client: &Client, slot: &Slot, len: usize) -> Option<Vec<u8>> {
+    let (attachment, hash) = slot.as_ref()?;
+    info!("{} {}", "Downloading".blue().bold(), attachment.filename);
+
+    let bytes = match _download(client, attachment).await {
+        Ok(v) => v,
+        Err(e) => {
+            warn!("Failed to download {}: {e}", attachment.filename);
+            return None;
+        }
+    };
+    if bytes.len() != len || hash.as_ref().is_some_and(|h| *h != part_hash(&bytes)) {
+        warn!("{} is corrupt", attachment.filename);
+        return None;
+    }
+    Some(bytes)
+}
+
+/// Downloads the parts of a parity group, followed by its parity parts when
+/// some part is broken or `check_parity` is set. Broken parts are rebuilt.
+/// Returns every part downloaded and the positions of the rebuilt ones.
+async fn _read_group(
+    http: &HttpPool,
+    data: &[Slot],
+    parity: &[Slot],
+    lens: &[usize],
+    check_parity: bool,
+) -> Result<(Vec<Vec<u8>>, Vec<usize>)> {
+    let shard_len = lens[0];
+    let concurrency = http.concurrency();
+
+    let mut shards: Vec<Option<Vec<u8>>> = stream::iter(data.iter().zip(lens))
+        .map(|(slot, len)| _fetch_part(http.client(), slot, *len))
+        .buffered(concurrency)
+        .collect()
+        .await;
+    if check_parity || shards.iter().any(Option::is_none) {
+        let parity: Vec<Option<Vec<u8>>> = stream::iter(parity)
+            .map(|slot| _fetch_part(http.client(), slot, shard_len))
+            .buffered(concurrency)
+            .collect()
+            .await;
+        shards.extend(parity);
+    }
+
+    let broken: Vec<usize> = (0..shards.len()).filter(|i| shards[*i].is_none()).collect();
+    if broken.is_empty() {
+        return Ok((shards.into_iter().flatten().collect(), broken));
+    }
+
+    // The last part of a file is shorter, parity is computed as if it was padded
+    for shard in shards.iter_mut().take(data.len()).flatten() {
+        shard.resize(shard_len, 0);
+    }
+    ReedSolomon::new(data.len(), parity.len())?.reconstruct(&mut shards)?;
+
+    let mut shards: Vec<Vec<u8>> = shards.into_iter().flatten().collect();
+    for (shard, len) in shards.iter_mut().zip(lens) {
+        shard.truncate(*len);
+    }
+    Ok((shards, broken))
+}
+
+/// Fetches the messages of an entry whose positions fall in `range`, in order.
+/// The head message is at position 0. Uses the part index when the manifest
+/// has one, and walks the `next` chain for older uploads.
+pub(crate) async fn _get_part_messages(
+    http: &HttpPool,
+    head: Message,
+    entry: &FileEntry,
+    range: Range<usize>,
+) -> Result<Vec<Message>> {
+    if entry.parts.is_empty() {
+        let mut out = Vec::new();
+        let mut next = entry.next;
+        let mut next_channel = entry.next_channel;
+        let mut msg = head;
+        let mut i = 0;
+
+        loop {
+            let channel = next_channel.unwrap_or(msg.channel_id.get());
+            if range.contains(&i) {
+                out.push(msg);
+            }
+            i += 1;
+
+            let id = match next {
+                Some(id) if i < range.end => id,
+                _ => break,
+            };
+            msg = _get_message(http, channel, id).await?;
+            let entry = FileEntry::from_str(&msg.content)?;
+            (next, next_channel) = (entry.next, entry.next_channel);
+        }
+        return Ok(out);
+    }
+
+    let head = &head;
+    let out = stream::iter(
+        entry
+            .parts
+            .iter()
+            .copied()
+            .take(range.end)
+            .skip(range.start),
+    )
+    .map(|part| async move {
+        let channel = part.channel.unwrap_or(head.channel_id.get());
+        match part.id == head.id.get() {
+            true => Ok(head.clone()),
+            false => _get_message(http, channel, part.id).await,
+        }
+    })
+    .buffered(http.concurrency())
+    .try_collect()
+    .await?;
+    Ok(out)
+}
+
+/// Fetches a message with whichever client in the pool has the most budget left.
+async fn _get_message(http: &HttpPool, channel: u64, id: u64) -> Result<Message, serenity::Error> {
+    let client = http
+        .pick(Route::ChannelMessage {
+            channel_id: channel.into(),
+            message_id: id.into(),
+        })
+        .await;
+    client.get_message(channel.into(), id.into()).await
+}
+
+pub async fn cat(
+    message_id: u64,
+    offset: u64,
+    length: Option<u64>,
+    token: Option<String>,
+    channel: Option<u64>,
+    remote: Option<String>,
+    dir: Option<PathBuf>,
+) -> Result<()> {
+    let (http, channels, _) = _connect(token, channel, remote.as_deref(), dir)?;
+    let channel = channels[0];
+
+    let (msg, entry, _, _) = _get_download_variables(&http, message_id, channel).await?;
+    let size = entry.size.ok_or(DistoreError::IncompleteManifest("size"))?;
+    let part_size = entry.part_size.unwrap_or(PART_SIZE as u64);
+    if entry.compression.is_some() {
+        return _cat_compressed(&http, msg, &entry, offset, length).await;
+    }
+
+    let end = match length {
+        Some(length) => size.min(offset.saturating_add(length)),
+        None => size,
+    };
+    if offset >= end {
+        return Ok(());
+    }
+    let slots = _range_slots(&http, msg, &entry, offset, end).await?;
+
+    let client = http.client();
+    let mut stdout = io::stdout().lock();
+    let mut offset = offset;
+    while offset < end {
+        let len = part_size.min(end - offset);
+        let buf = _read_slots(client, &slots, part_size, offset, offset + len).await?;
+        stdout.write_all(&buf)?;
+        offset += len;
+    }
+    stdout.flush()?;
+
+    Ok(())
+}
+
+/// Prints `length` bytes of a compressed entry from `offset` on. The entry is
+/// inflated from its start, as there's no telling where an offset lands in
+/// the compressed parts.
+async fn _cat_compressed(
+    http: &HttpPool,
+    msg: Message,
+    entry: &FileEntry,
+    offset: u64,
+    length: Option<u64>,
+) -> Result<()> {
+    let size = entry.size.ok_or(DistoreError::IncompleteManifest("size"))?;
+    let part_size = entry.part_size.unwrap_or(PART_SIZE as u64);
+    let end = length.map_or(u64::MAX, |l| offset.saturating_add(l));
+    if size == 0 || offset >= end {
+        return Ok(());
+    }
+    let slots = _range_slots(http, msg, entry, 0, size).await?;
+
+    let mut decoder = DeflateDecoder::new(Vec::new());
+    let mut stdout = io::stdout().lock();
+    // Position in the compressed entry and in the inflated one
+    let (mut read, mut pos) = (0, 0);
+    while pos < end {
+        let chunk = match read < size {
+            true => {
+                let len = part_size.min(size - read);
+                read += len;
+                Some(_read_slots(http.client(), &slots, part_size, read - len, read).await?)
+            }
+            false => None,
+        };
+        let done = chunk.is_none();
+
+        let buf = _inflate(&mut decoder, chunk.as_deref())?;
+        let start = offset.saturating_sub(pos).min(buf.len() as u64) as usize;
+        let stop = end.saturating_sub(pos).min(buf.len() as u64) as usize;
+        stdout.write_all(&buf[start..stop])?;
+        pos += buf.len() as u64;
+
+        if done {
+            break;
+        }
+    }
+    stdout.flush()?;
+
+    Ok(())
+}
+
+pub async fn serve_http(
+    address: SocketAddr,
+    token: Option<String>,
+    channel: Option<u64>,
+    remote: Option<String>,
+    dir: Option<PathBuf>,
+) -> Result<()> {
+    _init_logger();
+    let (http, channels, _) = _connect(token, channel, remote.as_deref(), dir)?;
+
+    crate::server::serve(Arc::new(http), channels[0], address).await
+}
+
+/// Reads `len` bytes of a file starting at `offset`. Only the parts covering
+/// the range are downloaded, using HTTP range requests on their attachments.
+/// Compressed files can't be read this way.
+pub async fn read_range(
+    http: &HttpPool,
+    channel: u64,
+    message_id: u64,
+    offset: u64,
+    len: u64,
+) -> Result<Vec<u8>> {
+    let (msg, entry, _, _) = _get_download_variables(http, message_id, channel).await?;
+    let size = entry.size.ok_or(DistoreError::IncompleteManifest("size"))?;
+    let part_size = entry.part_size.unwrap_or(PART_SIZE as u64);
+    if entry.compression.is_some() {
+        return Err(DistoreError::CompressedRange);
+    }
+
+    let end = size.min(offset.saturating_add(len));
+    if offset >= end {
+        return Ok(Vec::new());
+    }
+
+    let slots = _range_slots(http, msg, &entry, offset, end).await?;
+    _read_slots(http.client(), &slots, part_size, offset, end).await
+}
+
+/// Fetches the messages holding the parts of an entry that cover
+/// `offset..end`, so the range can be read in as many chunks as needed.
+pub(crate) async fn _range_slots(
+    http: &HttpPool,
+    head: Message,
+    entry: &FileEntry,
+    offset: u64,
+    end: u64,
+) -> Result<Vec<Slot>> {
+    let part_size = entry.part_size.unwrap_or(PART_SIZE as u64);
+    let first = (offset / part_size) as usize;
+    let last = (end.saturating_sub(1) / part_size) as usize;
+    let (first_msg, last_msg) = match entry.parts.is_empty() {
+        true => (first / PARTS_PER_MESSAGE, last / PARTS_PER_MESSAGE),
+        false => {
+            // The first part of a valid manifest starts at offset 0, so every
+            // offset falls in some part
+            let find = |at| {
+                entry
+                    .parts
+                    .partition_point(|p| p.offset <= at)
+                    .checked_sub(1)
+                    .ok_or(DistoreError::UncoveredOffset(at))
+            };
+            (find(offset)?, find(end.saturating_sub(1))?)
+        }
+    };
+
+    let messages = _get_part_messages(http, head, entry, first_msg..last_msg + 1).await?;
+    Ok(_part_slots(messages.iter(), ".part", last + 1))
+}
+
+/// Reads `offset..end` of an entry from the slots returned by
+/// [`_range_slots`], using HTTP range requests on the attachments.
+pub(crate) async fn _read_slots(
+    client: &Client,
+    slots: &[Slot],
+    part_size: u64,
+    offset: u64,
+    end: u64,
+) -> Result<Vec<u8>> {
+    let first = (offset / part_size) as usize;
+    let mut out = Vec::with_capacity(end.saturating_sub(offset) as usize);
+    for (i, slot) in slots.iter().enumerate().skip(first) {
+        let part_start = i as u64 * part_size;
+        if part_start >= end {
+            break;
+        }
+        let (attachment, _) = slot
+            .as_ref()
+            .ok_or(DistoreError::Integrity(format!("Missing part {i}")))?;
+
+        let start = offset.max(part_start) - part_start;
+        let stop = end.min(part_start + part_size) - part_start;
+
+        let res = client
+            .get(&attachment.url)
+            .header(RANGE, format!("bytes={}-{}", start, stop - 1))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let partial = res.status() == StatusCode::PARTIAL_CONTENT;
+        let bytes = res.bytes().await?;
+        match partial {
+            true => out.extend_from_slice(&bytes),
+            false => out.extend_from_slice(bytes.get(start as usize..stop as usize).ok_or(
+                DistoreError::Integrity(format!("Part {i} is shorter than expected")),
+            )?),
+        }
+    }
+
+    Ok(out)
+}
+
+pub async fn list(
+    token: Option<String>,
+    channel: Option<u64>,
+    remote: Option<String>,
+    dir: Option<PathBuf>,
+) -> Result<()> {
+    _init_logger();
+    let (http, channels, _) = _connect(token, channel, remote.as_deref(), dir)?;
+    let channel = channels[0];
+
+    let channel = http.get_channel(channel.into()).await?.id();
+
+    info!("Retrieving messages...");
+
+    let list = list_internal(channel.into(), &http).await?;
+
+    for entry in list {
+        println!(
+            "{}: {}\n    {}: {}\n    {}: {}",
+            "ID".bold(),
+            entry.1,
+            "Name".bold(),
+            entry.0.name.unwrap_or_default(),
+            "Size".bold(),
+            HumanBytes(entry.0.size.unwrap_or_default())
+        );
+    }
+    Ok(())
+}
+
+/// Lists every server the bot is in, with the channels it can write to and
+/// how much is stored in each of them. Threads are only listed while active,
+/// and a file stored in a listed thread is counted on the line of the thread
+/// only.
+pub async fn channels(
+    token: Option<String>,
+    remote: Option<String>,
+    dir: Option<PathBuf>,
+) -> Result<()> {
+    _init_logger();
+    let path = config_path(dir)?;
+    let tokens = _tokens(&path, token, remote.as_deref())?;
+
+    let settings = ConfigValue::get_current_settings(&path, remote.as_deref())?;
+    let http = HttpPool::with_settings(&tokens, &settings)?;
+    let user = http.get_current_user().await?;
+
+    info!("Retrieving channels...");
+
+    for info in _get_guilds(&http).await? {
+        println!("{} ({})", info.name.bold(), info.id);
+
+        let writable = match _writable_channels(&http, info.id, user.id).await {
+            Ok(writable) => writable,
+            Err(e) => {
+                println!("    {}", e.to_string().red());
+                continue;
+            }
+        };
+
+        let http = &http;
+        let mut counts: Vec<_> = stream::iter(writable)
+            .map(|channel| async move {
+                let entries = list_internal(channel.id.get(), http).await;
+                (channel, entries)
+            })
+            .buffered(http.concurrency())
+            .collect()
+            .await;
+
+        // The head of a thread shares its ID and is listed by the parent too,
+        // so only top-level heads are counted on the line of the parent
+        let threads: HashSet<u64> = counts
+            .iter()
+            .filter(|(c, _)| c.thread_metadata.is_some())
+            .map(|(c, _)| c.id.get())
+            .collect();
+        let mut starters = HashMap::new();
+        for (channel, entries) in &mut counts {
+            let Ok(entries) = entries else { continue };
+            if channel.thread_metadata.is_none() {
+                let (moved, kept): (Vec<_>, _) = std::mem::take(entries)
+                    .into_iter()
+                    .partition(|(_, id)| threads.contains(id));
+                *entries = kept;
+                starters.extend(moved.into_iter().map(|(e, id)| (id, e)));
+            }
+        }
+        for (channel, entries) in &mut counts {
+            let Ok(entries) = entries else { continue };
+            let id = channel.id.get();
+            if let Some(entry) = starters.remove(&id) {
+                if !entries.iter().any(|(_, i)| *i == id) {
+                    entries.push((entry, id));
+                }
+            }
+        }
+
+        for (channel, entries) in counts {
+            let name = match channel.thread_metadata {
+                Some(_) => format!("{} (thread)", channel.name),
+                None => format!("#{}", channel.name),
+            };
+            match entries {
+                Ok(entries) => println!(
+                    "    {name} ({}): {} file(s), {}",
+                    channel.id,
+                    entries.len(),
+                    HumanBytes(entries.iter().filter_map(|(e, _)| e.size).sum())
+                ),
+                Err(e) => println!("    {name} ({}): {}", channel.id, e.to_string().red()),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the text and forum channels of `guild` that `bot` can send messages
+/// to, each followed by its active threads that `bot` can write in.
+async fn _writable_channels(http: &Http, guild: GuildId, bot: UserId) -> Result<Vec<GuildChannel>> {
+    let partial = http.get_guild(guild).await?;
+    let member = http.get_member(guild, bot).await?;
+    let mut channels: Vec<_> = http
+        .get_channels(guild)
+        .await?
+        .into_iter()
+        .filter(|c| matches!(c.kind, ChannelType::Text | ChannelType::Forum))
+        .collect();
+    channels.sort_by_key(|c| c.position);
+
+    let threads = guild.get_active_threads(http).await?.threads;
+    let mut writable = Vec::new();
+    for channel in channels {
+        let permissions = partial.user_permissions_in(&channel, &member);
+        if !permissions.contains(Permissions::VIEW_CHANNEL) {
+            continue;
+        }
+        if permissions.contains(Permissions::SEND_MESSAGES) {
+            writable.push(channel.clone());
+        }
+        if channel.kind == ChannelType::Forum
+            || !permissions.contains(Permissions::SEND_MESSAGES_IN_THREADS)
+        {
+            continue;
+        }
+        writable.extend(
+            threads
+                .iter()
+                .filter(|t| t.parent_id == Some(channel.id))
+                .cloned(),
+        );
+    }
+    Ok(writable)
+}
+
+/// Lists the entries of `channel`. Forum channels hold every entry in a post
+/// of its own, so their posts are listed instead of the message history. The
+/// threads of text channels are listed along with their message history.
+pub(crate) async fn list_internal(channel: u64, http: &HttpPool) -> Result<Vec<(FileEntry, u64)>> {
+    let mut out = Vec::new();
+
+    for msg in _get_heads(channel, http).await? {
+        let entry = match FileEntry::from_str(&msg.content) {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!("Skipping malformed manifest in message {}: {e}", msg.id);
+                continue;
+            }
+        };
+        if entry.name.is_none() {
+            continue;
+        }
+        if entry.size.is_none() {
+            warn!("Skipping manifest without a size in message {}", msg.id);
+            continue;
+        }
+
+        out.push((entry, msg.id.into()))
+    }
+    Ok(out)
+}
+
+/// Fetches the messages of `channel` that look like head messages, without
+/// reading their manifests.
+async fn _get_heads(channel: u64, http: &HttpPool) -> Result<Vec<Message>> {
+    let messages = match http.get_channel(channel.into()).await? {
+        Channel::Guild(c) if c.kind == ChannelType::Forum => _get_thread_heads(&c, http).await?,
+        Channel::Guild(c) if c.kind == ChannelType::Text => {
+            let mut messages = _get_messages(c.id, http).await?;
+            let listed: HashSet<u64> = messages.iter().map(|m| m.id.get()).collect();
+            let heads = _get_thread_heads(&c, http).await?;
+            messages.extend(heads.into_iter().filter(|m| !listed.contains(&m.id.get())));
+            messages
+        }
+        _ => _get_messages(channel.into(), http).await?,
+    };
+    Ok(messages
+        .into_iter()
+        .filter(|m| m.author.bot && m.content.starts_with(MANIFEST_HEADER))
+        .collect())
+}
+
+/// Fetches the starter message of every active and archived thread of `channel`.
+async fn _get_thread_heads(channel: &GuildChannel, http: &HttpPool) -> Result<Vec<Message>> {
+    let threads = _get_threads(channel, http).await?;
+
+    // The starter message of a thread shares its ID. Forum posts hold theirs,
+    // while threads of text channels start from a message of the channel
+    let home = |thread: &GuildChannel| match channel.kind {
+        ChannelType::Forum => thread.id,
+        _ => channel.id,
+    };
+    let heads = stream::iter(threads)
+        .map(|thread| async move {
+            http.get_message(home(&thread), thread.id.get().into())
+                .await
+                .inspect_err(|e| warn!("Skipping thread {}: {e}", thread.id))
+                .ok()
+        })
+        .buffered(http.concurrency())
+        .filter_map(|msg| async move { msg })
+        .collect()
+        .await;
+    Ok(heads)
+}
+
+/// Fetches every active and archived public thread of `channel`, or every
+/// post of a forum channel.
+async fn _get_threads(channel: &GuildChannel, http: &Http) -> Result<Vec<GuildChannel>> {
+    let mut threads: Vec<GuildChannel> = channel
+        .guild_id
+        .get_active_threads(http)
+        .await?
+        .threads
+        .into_iter()
+        .filter(|t| t.parent_id == Some(channel.id))
+        .collect();
+
+    let mut before = None;
+    loop {
+        let archived = channel
+            .id
+            .get_archived_public_threads(http, before, Some(100))
+            .await?;
+        before = archived
+            .threads
+            .last()
+            .and_then(|t| t.thread_metadata)
+            .and_then(|m| m.archive_timestamp)
+            .map(|t| t.unix_timestamp() as u64);
+        threads.extend(archived.threads);
+
+        if !archived.has_more || before.is_none() {
+            break;
+        }
+    }
+    Ok(threads)
+}
+
+pub async fn check_update() -> Result<()> {
+    let url = "https://crates.io/api/v1/crates/distore";
+
+    let res = Client::new()
+        .get(url)
+        .header("Content-Type", "application/json")
+        .header("Accept", "application/json")
+        .header("User-Agent", format!("distore/{}", *VERSION))
+        .send()
+        .await?;
+
+    let error = res.error_for_status();
+    match error {
+        Ok(res) => {
+            let json = res.json::<Value>().await?;
+
+            let latest = Version::from_str(
+                json["crate"]["newest_version"]
+                    .to_string()
+                    .replace("\"", "")
+                    .as_str(),
+            )?;
+
+            if latest > *VERSION {
+                println!("New version available: v{} -> v{latest}", *VERSION);
+                println!("Run this command to update: cargo install distore");
+            } else {
+                println!("Already at the latest version: v{latest}");
+            }
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub async fn delete(
+    message_id: u64,
+    token: Option<String>,
+    channel: Option<u64>,
+    remote: Option<String>,
+    dir: Option<PathBuf>,
+) -> Result<()> {
+    _init_logger();
+    let (http, channels, _) = _connect(token, channel, remote.as_deref(), dir)?;
+    let channel = channels[0];
+
+    delete_internal(&http, message_id, channel, || {}).await
+}
+
+pub(crate) async fn delete_internal<F: Fn()>(
+    http: &HttpPool,
+    message_id: u64,
+    channel_id: u64,
+    callback: F,
+) -> Result<()> {
+    let msg = _get_head(http, channel_id, message_id).await?;
+
+    let entry = FileEntry::from_str(&msg.content)?;
+    entry.len.ok_or(DistoreError::IncompleteManifest("len"))?;
+
+    let mut messages = _get_entry_messages(http, msg, entry.clone()).await?;
+    for replica in entry.replicas.iter() {
+        let res = async {
+            let msg = _get_head(http, replica.channel, replica.id).await?;
+            let entry = FileEntry::from_str(&msg.content)?;
+            _get_entry_messages(http, msg, entry).await
+        }
+        .await;
+        match res {
+            Ok(v) => messages.extend(v),
+            Err(e) => warn!("Skipping the copy in channel {}: {e}", replica.channel),
+        }
+    }
+    info!("Deleting {} message(s)...", messages.len());
+
+    let mut heads = vec![message_id];
+    heads.extend(entry.replicas.iter().map(|r| r.id));
+    _delete_messages(http, messages, &heads, callback).await
+}
+
+/// Fetches every message of a copy of an entry, including the parity parts
+/// and the part index. Parts that are already gone are skipped when the entry
+/// has a part index.
+async fn _get_entry_messages(
+    http: &HttpPool,
+    head: Message,
+    mut entry: FileEntry,
+) -> Result<Vec<Message>> {
+    let index = match _load_index(http, &head, &mut entry).await {
+        Ok(index) => index,
+        Err(e) => {
+            warn!("Following the message chain, the part index can't be read: {e}");
+            None
+        }
+    };
+    let (data, parity) = _get_surviving_messages(http, head, &entry).await?;
+    Ok(data
+        .into_iter()
+        .chain(parity)
+        .flatten()
+        .chain(index)
+        .collect())
+}
+
+/// Deletes `messages`. The head messages in `heads` go last, so an
+/// interrupted delete leaves the entry listed and it can be deleted again.
+/// The threads and forum posts the messages started go after them, as they
+/// would be left empty.
+async fn _delete_messages<F: Fn()>(
+    http: &HttpPool,
+    messages: Vec<Message>,
+    heads: &[u64],
+    callback: F,
+) -> Result<()> {
+    let threads: Vec<u64> = messages
+        .iter()
+        .filter(|m| m.thread.is_some() || m.channel_id.get() == m.id.get())
+        .map(|m| m.id.get())
+        .collect();
+    let (heads, parts): (Vec<Message>, Vec<Message>) = messages
+        .into_iter()
+        .partition(|m| heads.contains(&m.id.get()));
+
+    for batch in [parts, heads] {
+        let mut deletions = stream::iter(batch)
+            .map(|msg| async move { msg.delete(http.client_for(&msg)).await })
+            .buffer_unordered(http.concurrency());
+
+        while let Some(res) = deletions.next().await {
+            res?;
+            callback();
+        }
+    }
+
+    for thread in threads {
+        if let Err(e) = http.delete_channel(thread.into(), None).await {
+            warn!("Failed to delete thread {thread}: {e}");
+        }
+    }
+    Ok(())
+}
+
+pub async fn rename(
+    message_id: u64,
+    new_name: String,
+    token: Option<String>,
+    channel: Option<u64>,
+    remote: Option<String>,
+    dir: Option<PathBuf>,
+) -> Result<()> {
+    _init_logger();
+    let (http, channels, _) = _connect(token, channel, remote.as_deref(), dir)?;
+    let channel = channels[0];
+
+    let (old_name, skipped) = rename_internal(&http, message_id, channel, &new_name).await?;
+
+    println!("{} {old_name} -> {new_name}", "Renamed".green().bold());
+    for (replica, e) in skipped {
+        println!(
+            "{} the copy in channel {}, it keeps the old name: {e}",
+            "Skipped".red().bold(),
+            replica.channel
+        );
+    }
+    Ok(())
+}
+
+/// Rewrites the `name` of the head message of every copy in place, the one
+/// of `message_id` last. Copies that can't be read or written are skipped,
+/// as they may be gone. Returns the old name and the copies skipped, with
+/// what went wrong.
+pub(crate) async fn rename_internal(
+    http: &HttpPool,
+    message_id: u64,
+    channel: u64,
+    new_name: &str,
+) -> Result<(String, Vec<(MessageRef, DistoreError)>)> {
+    check_name(new_name)?;
+
+    let mut msg = _get_head(http, channel, message_id).await?;
+    let mut entry = FileEntry::from_str(&msg.content)?;
+    let old_name = entry
+        .name
+        .replace(new_name.into())
+        .ok_or(DistoreError::IncompleteManifest("name"))?;
+
+    info!("{} {old_name} -> {new_name}", "Renaming".blue().bold());
+    let mut skipped = Vec::new();
+    for replica in entry.replicas.iter() {
+        let res = async {
+            let mut msg = _get_head(http, replica.channel, replica.id).await?;
+            let mut entry = FileEntry::from_str(&msg.content)?;
+            entry.name = Some(new_name.into());
+            _write_head(http, &mut msg, &mut entry).await
+        }
+        .await;
+        if let Err(e) = res {
+            skipped.push((*replica, e));
+        }
+    }
+    _write_head(http, &mut msg, &mut entry).await?;
+
+    Ok((old_name, skipped))
+}
+
+pub async fn replicate(
+    message_id: u64,
+    to_channel: Vec<u64>,
+    token: Option<String>,
+    channel: Option<u64>,
+    remote: Option<String>,
+    dir: Option<PathBuf>,
+) -> Result<()> {
+    _init_logger();
+    let path = config_path(dir.clone())?;
+    let (http, channels, settings) = _connect(token, channel, remote.as_deref(), dir)?;
+    let channel = channels[0];
+    let targets = match to_channel.is_empty() {
+        true => get_replicas_internal(&path, remote.as_deref())?,
+        false => to_channel,
+    };
+
+    let copies = replicate_internal(
+        &http,
+        message_id,
+        channel,
+        &targets,
+        settings.cache_dir.as_deref(),
+    )
+    .await?;
+
+    match copies.is_empty() {
+        true => println!("{} already has a copy in every channel", message_id),
+        false => println!(
+            "{} {} to {} channel(s)",
+            "Replicated".green().bold(),
+            message_id,
+            copies.len()
+        ),
+    }
+    Ok(())
+}
+
+/// Uploads a copy of an existing entry to each of `targets` that doesn't have
+/// one yet, then links every copy to the others. Returns the new head messages.
+pub(crate) async fn replicate_internal(
+    http: &HttpPool,
+    message_id: u64,
+    channel: u64,
+    targets: &[u64],
+    cache_dir: Option<&Path>,
+) -> Result<Vec<Message>> {
+    let (msg, entry, name, _) = _get_download_variables(http, message_id, channel).await?;
+
+    let mut copies = vec![(msg, entry.clone())];
+    for replica in entry.replicas.iter() {
+        match _get_download_variables(http, replica.id, replica.channel).await {
+            Ok((msg, entry, _, _)) => copies.push((msg, entry)),
+            Err(e) => warn!("Dropping the copy in channel {}: {e}", replica.channel),
+        }
+    }
+
+    let targets: Vec<u64> = targets
+        .iter()
+        .copied()
+        .filter(|t| copies.iter().all(|(msg, _)| msg.channel_id.get() != *t))
+        .collect();
+    if targets.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let cache_dir = _cache_dir(cache_dir)?;
+    let part_size = entry.part_size.map_or(PART_SIZE, |p| p as usize);
+    let file = cache_dir.join(_file_name(&name)?);
+    _download_stored(http, copies[0].0.clone(), &entry, &file, &|_| {}).await?;
+    let (part_paths, _, _, sha256) =
+        disassemble_internal(file.clone(), cache_dir.clone(), part_size, |_, _| {})?;
+    fs::remove_file(&file)?;
+
+    if entry.sha256.as_ref().is_some_and(|h| *h != sha256) {
+        for part in part_paths.iter() {
+            fs::remove_file(part)?;
+        }
+        return Err(DistoreError::Integrity(
+            "Downloaded file doesn't match its hash".into(),
+        ));
+    }
+    let parity_paths = match entry.parity {
+        Some(parity) if parity > 0 => _write_parity_parts(&part_paths, parity, &cache_dir, &name)?,
+        _ => Vec::new(),
+    };
+    let files: Vec<PathBuf> = part_paths.iter().chain(&parity_paths).cloned().collect();
+
+    let head = FileEntry {
+        name: Some(name),
+        size: entry.size,
+        len: Some(part_paths.len()),
+        part_size: Some(part_size as u64),
+        sha256: Some(sha256),
+        compression: entry.compression.clone(),
+        original_size: entry.original_size,
+        parity: entry.parity,
+        parity_group: entry.parity.map(|_| PARITY_GROUP),
+        ..Default::default()
+    };
+
+    let mut messages = Vec::new();
+    let res = async {
+        let mut new = Vec::new();
+        for target in targets {
+            info!("{} to channel {target}", "Replicating".blue().bold());
+            let (copy, entry) = _upload_parts(
+                http,
+                head.clone(),
+                &part_paths,
+                &parity_paths,
+                Destination::Channels(&[target]),
+                &mut messages,
+                &mut Vec::new(),
+                &mut Vec::new(),
+                |_, _| {},
+            )
+            .await?;
+            new.push(copy[0].clone());
+            copies.push((copy[0].clone(), entry));
+        }
+        _link_replicas(http, copies, &mut messages).await?;
+        Ok(new)
+    }
+    .await;
+
+    let new = match res {
+        Ok(v) => v,
+        Err(e) => {
+            _rollback(http, messages, &[], &files).await;
+            return Err(e);
+        }
+    };
+
+    for part in files {
+        fs::remove_file(part)?;
+    }
+
+    Ok(new)
+}
+
+pub async fn repair(
+    message_id: u64,
+    token: Option<String>,
+    channel: Option<u64>,
+    remote: Option<String>,
+    dir: Option<PathBuf>,
+) -> Result<()> {
+    _init_logger();
+    let (http, channels, _) = _connect(token, channel, remote.as_deref(), dir)?;
+    let channel = channels[0];
+
+    match repair_internal(&http, message_id, channel).await? {
+        0 => println!("{} {message_id} is intact", "OK".green().bold()),
+        n => println!("{} {n} part(s) of {message_id}", "Repaired".green().bold()),
+    }
+    Ok(())
+}
+
+/// Rebuilds the broken parts and parity parts of an entry and uploads them
+/// again. Messages that are gone are sent again and the chain is relinked
+/// around them. Returns the number of parts that were fixed.
+pub(crate) async fn repair_internal(
+    http: &HttpPool,
+    message_id: u64,
+    channel: u64,
+) -> Result<usize> {
+    let (head, mut entry, name, len) = _get_download_variables(http, message_id, channel).await?;
+    let parity = entry
+        .parity
+        .filter(|p| *p > 0)
+        .ok_or(DistoreError::NoParity(name.clone()))?;
+    let group = entry.parity_group.unwrap_or(PARITY_GROUP);
+    let parity_len = len.div_ceil(group) * parity;
+
+    let (data, parity_messages) = _get_surviving_messages(http, head.clone(), &entry).await?;
+    let data_slots = _part_slots(data.iter().flatten(), ".part", len);
+    let parity_slots = _part_slots(parity_messages.iter().flatten(), ".parity", parity_len);
+
+    let mut fixed_data = HashMap::new();
+    let mut fixed_parity = HashMap::new();
+    for (g, slots) in data_slots.chunks(group).enumerate() {
+        let start = g * group;
+        let lens: Vec<usize> = (start..start + slots.len())
+            .map(|i| _part_len(&entry, i))
+            .collect();
+        let parity_group = &parity_slots[g * parity..(g + 1) * parity];
+
+        let (mut shards, rebuilt) = _read_group(http, slots, parity_group, &lens, true).await?;
+        for i in rebuilt {
+            let shard = std::mem::take(&mut shards[i]);
+            match i < slots.len() {
+                true => fixed_data.insert(start + i, shard),
+                false => fixed_parity.insert(g * parity + i - slots.len(), shard),
+            };
+        }
+    }
+
+    let repaired = fixed_data.len() + fixed_parity.len();
+    if repaired == 0 {
+        return Ok(0);
+    }
+    info!("{} {repaired} part(s)", "Repairing".blue().bold());
+
+    let original = entry.clone();
+    let mut locations: Vec<MessageRef> = match entry.parts.is_empty() {
+        true => data
+            .iter()
+            .flatten()
+            .map(|m| MessageRef {
+                id: m.id.get(),
+                channel: m.channel_id.get(),
+            })
+            .collect(),
+        false => entry
+            .parts
+            .iter()
+            .map(|p| MessageRef {
+                id: p.id,
+                channel: p.channel.unwrap_or(head.channel_id.get()),
+            })
+            .collect(),
+    };
+    let old_locations = locations.clone();
+
+    // Go backwards so a replaced message can point to the final next message
+    for m in (0..data.len()).rev() {
+        let range = m * PARTS_PER_MESSAGE..((m + 1) * PARTS_PER_MESSAGE).min(len);
+        match &data[m] {
+            Some(msg) => _replace_parts(http, msg, &name, ".part", range, &mut fixed_data).await?,
+            None => {
+                let next = locations.get(m + 1).copied();
+                let content = FileEntry {
+                    next: next.map(|n| n.id),
+                    next_channel: next
+                        .map(|n| n.channel)
+                        .filter(|c| *c != locations[m].channel),
+                    ..Default::default()
+                };
+                let msg = _resend_parts(
+                    http,
+                    locations[m].channel,
+                    &name,
+                    ".part",
+                    range,
+                    &mut fixed_data,
+                    content,
+                )
+                .await?;
+                locations[m].id = msg.id.get();
+            }
+        }
+    }
+
+    // Point the surviving messages before a replaced one to it
+    for m in 1..data.len().saturating_sub(1) {
+        let Some(msg) = &data[m] else {
+            continue;
+        };
+        if locations[m + 1] == old_locations[m + 1] {
+            continue;
+        }
+        let mut content = FileEntry::from_str(&msg.content)?;
+        content.next = Some(locations[m + 1].id);
+        msg.clone()
+            .edit(
+                http.client_for(msg),
+                EditMessage::new().content(content.to_string()),
+            )
+            .await?;
+    }
+
+    for (m, msg) in parity_messages.iter().enumerate() {
+        let range = m * PARTS_PER_MESSAGE..((m + 1) * PARTS_PER_MESSAGE).min(parity_len);
+        match msg {
+            Some(msg) => {
+                _replace_parts(http, msg, &name, ".parity", range, &mut fixed_parity).await?
+            }
+            None => {
+                let location = &mut entry.parity_messages[m];
+                let msg = _resend_parts(
+                    http,
+                    location.channel,
+                    &name,
+                    ".parity",
+                    range,
+                    &mut fixed_parity,
+                    FileEntry::default(),
+                )
+                .await?;
+                location.id = msg.id.get();
+            }
+        }
+    }
+
+    for (part, location) in entry.parts.iter_mut().zip(locations.iter()) {
+        part.id = location.id;
+    }
+    if let Some(next) = locations.get(1) {
+        entry.next = Some(next.id);
+    }
+    if entry != original {
+        _write_head(http, &mut head.clone(), &mut entry).await?;
+    }
+
+    Ok(repaired)
+}
+
+/// Swaps the broken attachments of `msg` in `range` for their rebuilt versions.
+async fn _replace_parts(
+    http: &HttpPool,
+    msg: &Message,
+    name: &str,
+    suffix: &str,
+    range: Range<usize>,
+    fixed: &mut HashMap<usize, Vec<u8>>,
+) -> Result<()> {
+    let broken: Vec<usize> = range.filter(|n| fixed.contains_key(n)).collect();
+    if broken.is_empty() {
+        return Ok(());
+    }
+
+    // Readers find parts by name, but keep them in order all the same
+    let number = |a: &Attachment| {
+        a.filename
+            .rsplit_once(suffix)
+            .and_then(|(_, n)| n.parse::<usize>().ok())
+            .unwrap_or(usize::MAX)
+    };
+    let mut order: Vec<(usize, Option<AttachmentId>)> = msg
+        .attachments
+        .iter()
+        .filter(|a| !broken.contains(&number(a)))
+        .map(|a| (number(a), Some(a.id)))
+        .chain(broken.iter().map(|n| (*n, None)))
+        .collect();
+    order.sort_by_key(|(n, _)| *n);
+
+    let mut attachments = EditAttachments::new();
+    for (n, id) in order {
+        if let Some(id) = id {
+            attachments = attachments.keep(id);
+        } else if let Some(bytes) = fixed.remove(&n) {
+            attachments =
+                attachments.add(CreateAttachment::bytes(bytes, format!("{name}{suffix}{n}")));
+        }
+    }
+
+    msg.clone()
+        .edit(
+            http.client_for(msg),
+            EditMessage::new().attachments(attachments),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Sends the rebuilt parts of a message that is gone in a new message.
+async fn _resend_parts(
+    http: &HttpPool,
+    channel: u64,
+    name: &str,
+    suffix: &str,
+    range: Range<usize>,
+    fixed: &mut HashMap<usize, Vec<u8>>,
+    mut content: FileEntry,
+) -> Result<Message> {
+    let mut attachments = Vec::new();
+    for n in range {
+        let bytes = fixed.remove(&n).ok_or(DistoreError::Integrity(format!(
+            "Part {n} of a missing message couldn't be rebuilt"
+        )))?;
+        content.hashes.push(part_hash(&bytes));
+        attachments.push(CreateAttachment::bytes(bytes, format!("{name}{suffix}{n}")));
+    }
+
+    _send_attachments(http, channel, attachments, content.to_string()).await
+}
+
+/// Sends `attachments` in a new message with whichever client has the most
+/// budget left for `channel`.
+async fn _send_attachments(
+    http: &HttpPool,
+    channel: u64,
+    attachments: Vec<CreateAttachment>,
+    content: String,
+) -> Result<Message> {
+    let client = http
+        .pick(Route::ChannelMessages {
+            channel_id: channel.into(),
+        })
+        .await;
+    let msg = ChannelId::from(channel)
+        .send_files(&*client, attachments, CreateMessage::new().content(content))
+        .await?;
+    Ok(msg)
+}
+
+pub async fn cp(
+    message_id: u64,
+    to_channel: u64,
+    token: Option<String>,
+    channel: Option<u64>,
+    remote: Option<String>,
+    dir: Option<PathBuf>,
+) -> Result<()> {
+    _init_logger();
+    let (http, channels, _) = _connect(token, channel, remote.as_deref(), dir)?;
+    let channel = channels[0];
+
+    let (msg, _) = copy_internal(&http, message_id, channel, to_channel).await?;
+
+    println!(
+        "{} {message_id} to channel id {to_channel}. Message id: {}",
+        "Copied".green().bold(),
+        msg.id
+    );
+    Ok(())
+}
+
+pub async fn mv(
+    message_id: u64,
+    to_channel: u64,
+    token: Option<String>,
+    channel: Option<u64>,
+    remote: Option<String>,
+    dir: Option<PathBuf>,
+) -> Result<()> {
+    _init_logger();
+    let (http, channels, _) = _connect(token, channel, remote.as_deref(), dir)?;
+    let channel = channels[0];
+
+    let msg = move_internal(&http, message_id, channel, to_channel).await?;
+
+    println!(
+        "{} {message_id} to channel id {to_channel}. Message id: {}",
+        "Moved".green().bold(),
+        msg.id
+    );
+    Ok(())
+}
+
+/// Copies an entry into `target` without writing it to the disk. The parts of
+/// one message are downloaded at a time and sent straight to the target,
+/// which can't be a forum channel. Returns the new head message and its
+/// manifest.
+pub(crate) async fn copy_internal(
+    http: &HttpPool,
+    message_id: u64,
+    channel: u64,
+    target: u64,
+) -> Result<(Message, FileEntry)> {
+    // Forum channels only take posts, which a copy isn't sent as
+    if let Channel::Guild(c) = http.get_channel(target.into()).await? {
+        if c.kind == ChannelType::Forum {
+            return Err(DistoreError::ForumTarget(target));
+        }
+    }
+
+    let (head, entry, name, len) = _get_download_variables(http, message_id, channel).await?;
+    let parity_len = match (entry.parity, entry.parity_group) {
+        (Some(parity), group) => len.div_ceil(group.unwrap_or(PARITY_GROUP)) * parity,
+        _ => 0,
+    };
+
+    let data = _get_part_messages(http, head, &entry, 0..usize::MAX).await?;
+    let mut parity_messages = Vec::new();
+    for parity in entry.parity_messages.iter() {
+        parity_messages.push(_get_message(http, parity.channel, parity.id).await?);
+    }
+    let data_slots = _part_slots(data.iter(), ".part", len);
+    let parity_slots = _part_slots(parity_messages.iter(), ".parity", parity_len);
+    if data_slots.iter().chain(&parity_slots).any(Option::is_none) {
+        return Err(DistoreError::Integrity(format!(
+            "{name} is missing some of its parts"
+        )));
+    }
+
+    let head = FileEntry {
+        name: Some(name),
+        size: entry.size,
+        len: entry.len,
+        part_size: entry.part_size,
+        sha256: entry.sha256.clone(),
+        compression: entry.compression.clone(),
+        original_size: entry.original_size,
+        parity: entry.parity,
+        parity_group: entry.parity_group,
+        ..Default::default()
+    };
+
+    let mut sent = Vec::new();
+    let res = async {
+        let mut hashes = Vec::new();
+        let chunks = data_slots
+            .chunks(PARTS_PER_MESSAGE)
+            .chain(parity_slots.chunks(PARTS_PER_MESSAGE));
+        for chunk in chunks {
+            let parts: Vec<(Vec<u8>, String)> = stream::iter(chunk.iter().flatten())
+                .map(|(attachment, hash)| async move {
+                    info!("{} {}", "Copying".blue().bold(), attachment.filename);
+                    let bytes = _download(http.client(), attachment).await?;
+                    if hash.as_ref().is_some_and(|h| *h != part_hash(&bytes)) {
+                        return Err(DistoreError::Integrity(format!(
+                            "{} is corrupt",
+                            attachment.filename
+                        )));
+                    }
+                    Ok((bytes, attachment.filename.clone()))
+                })
+                .buffered(http.concurrency())
+                .try_collect()
+                .await?;
+
+            hashes.push(parts.iter().map(|(bytes, _)| part_hash(bytes)).collect());
+            let attachments = parts
+                .into_iter()
+                .map(|(bytes, name)| CreateAttachment::bytes(bytes, name))
+                .collect();
+            sent.push(_send_attachments(http, target, attachments, "tmp".into()).await?);
+        }
+
+        let mut data_sent = sent.clone();
+        let parity_sent = data_sent.split_off(data_slots.len().div_ceil(PARTS_PER_MESSAGE));
+        _link_parts(
+            http,
+            head,
+            data_sent,
+            parity_sent,
+            hashes,
+            &mut sent,
+            |_, _| {},
+        )
+        .await
+    }
+    .await;
+
+    match res {
+        Ok((messages, entry)) => Ok((messages[0].clone(), entry)),
+        Err(e) => {
+            _rollback(http, sent, &[], &[]).await;
+            Err(e)
+        }
+    }
+}
+
+/// Copies an entry into `target`, then deletes the source. Other copies of
+/// the entry are linked to the new one. Returns the new head message.
+pub(crate) async fn move_internal(
+    http: &HttpPool,
+    message_id: u64,
+    channel: u64,
+    target: u64,
+) -> Result<Message> {
+    let (head, entry, _, _) = _get_download_variables(http, message_id, channel).await?;
+    let (new_head, new_entry) = copy_internal(http, message_id, channel, target).await?;
+
+    if !entry.replicas.is_empty() {
+        let mut copies = vec![(new_head.clone(), new_entry)];
+        for replica in entry.replicas.iter() {
+            match _get_download_variables(http, replica.id, replica.channel).await {
+                Ok((msg, entry, _, _)) => copies.push((msg, entry)),
+                Err(e) => warn!("Dropping the copy in channel {}: {e}", replica.channel),
+            }
+        }
+        // The new copy is complete, there's nothing to roll back
+        _link_replicas(http, copies, &mut Vec::new()).await?;
+    }
+
+    let heads = [head.id.get()];
+    let messages = _get_entry_messages(http, head, entry).await?;
+    info!("Deleting {} message(s)...", messages.len());
+    _delete_messages(http, messages, &heads, || {}).await?;
+
+    Ok(new_head)
+}
+
+pub async fn verify(
+    message_id: Option<u64>,
+    deep: bool,
+    token: Option<String>,
+    channel: Option<u64>,
+    remote: Option<String>,
+    dir: Option<PathBuf>,
+) -> Result<()> {
+    _init_logger();
+    let (http, channels, _) = _connect(token, channel, remote.as_deref(), dir)?;
+    let channel = channels[0];
+
+    // Every head is checked on its own, so a bad manifest is reported along
+    // with the rest instead of stopping the run
+    let ids = match message_id {
+        Some(id) => vec![(id, channel)],
+        None => {
+            info!("Retrieving messages...");
+            _get_heads(channel, &http)
+                .await?
+                .into_iter()
+                .map(|m| (m.id.get(), m.channel_id.get()))
+                .collect()
+        }
+    };
+
+    let mut broken = 0;
+    for (id, channel) in ids {
+        let problems = verify_internal(&http, id, channel, deep).await;
+
+        if problems.is_empty() {
+            println!("{} {id}", "Intact".green().bold());
+            continue;
+        }
 
-    Ok((msg, entry, name, len))
+        broken += 1;
+        println!("{} {id}", "Broken".red().bold());
+        for problem in problems {
+            println!("    {problem}");
+        }
+    }
+
+    match broken {
+        0 => Ok(()),
+        _ => Err(DistoreError::Integrity(format!("{broken} broken entries"))),
+    }
 }
 
-pub(crate) async fn download_internal<F: Fn(f64)>(
-    http: &Http,
+/// Walks the chain of an entry and returns every problem found with it. With
+/// `deep`, the content is also downloaded and checked against the recorded hash.
+pub(crate) async fn verify_internal(
+    http: &HttpPool,
     message_id: u64,
     channel: u64,
-    output: Option<PathBuf>,
-    callback: F,
-) -> Result<PathBuf> {
-    let (msg, mut entry, name, len) = _get_download_variables(http, message_id, channel).await?;
+    deep: bool,
+) -> Vec<String> {
+    let mut problems = Vec::new();
 
-    let size = entry.size.unwrap();
-
-    let path = output.clone().unwrap_or(name.clone().into());
-    let mut out = File::create(&path)?;
+    let head = match _get_head(http, channel, message_id).await {
+        Ok(v) => v,
+        Err(e) => return vec![format!("Head message {message_id} is missing: {e}")],
+    };
+    let channel = head.channel_id.get();
+    let mut entry = match FileEntry::from_str(&head.content) {
+        Ok(v) => v,
+        Err(e) => return vec![format!("Invalid manifest in {message_id}: {e}")],
+    };
+    let (Some(len), Some(size)) = (entry.len, entry.size) else {
+        return vec![format!("{message_id} is not a Distore entry")];
+    };
+    if let Err(e) = _load_index(http, &head, &mut entry).await {
+        problems.push(format!(
+            "Part index {} can't be read: {e}",
+            entry.index.unwrap()
+        ));
+    }
 
-    let mut i = 0;
-    let mut msg = msg;
-    let mut progress = 0;
-    while entry.next.is_some() || i < len {
-        for part in msg.attachments.iter() {
-            info!("{} {}", "Downloading".blue().bold(), part.filename);
-            let part = part.download().await?;
+    let mut next = entry.next;
+    let mut next_channel = entry.next_channel.unwrap_or(channel);
+    let mut messages = vec![head];
+    while let Some(id) = next {
+        if messages.iter().any(|m| m.id.get() == id) {
+            problems.push(format!("Chain loops back to {id}"));
+            break;
+        }
 
-            progress += part.len();
-            let fraction = if size > 0 {
-                progress as f64 / size as f64
-            } else {
-                1.0
-            };
+        info!("{} {id}", "Checking".blue().bold());
+        let msg = match http.get_message(next_channel.into(), id.into()).await {
+            Ok(v) => v,
+            Err(e) => {
+                problems.push(format!(
+                    "Missing link to {id} in channel {next_channel}: {e}"
+                ));
+                break;
+            }
+        };
+        (next, next_channel) = match FileEntry::from_str(&msg.content) {
+            Ok(v) => (v.next, v.next_channel.unwrap_or(next_channel)),
+            Err(e) => {
+                problems.push(format!("Invalid manifest in {id}: {e}"));
+                (None, next_channel)
+            }
+        };
+        messages.push(msg);
+    }
 
-            let fraction = fraction.clamp(0.0, 1.0);
+    if !entry.parts.is_empty() {
+        let mut offset = 0;
+        let chain: Vec<PartRef> = messages
+            .iter()
+            .map(|m| {
+                let part = PartRef {
+                    id: m.id.into(),
+                    offset,
+                    channel: Some(m.channel_id.get()).filter(|c| *c != channel),
+                };
+                offset += m.attachments.iter().map(|a| a.size as u64).sum::<u64>();
+                part
+            })
+            .collect();
 
-            out.write_all(&part)?;
-            callback(fraction);
+        if chain != entry.parts {
+            problems.push("Part index does not match the message chain".to_string());
         }
-        i += msg.attachments.len();
+    }
 
-        if entry.next.is_none() {
-            continue;
+    let slots = _part_slots(messages.iter(), ".part", len);
+    let parts: Vec<&(Attachment, Option<String>)> = slots.iter().flatten().collect();
+    if parts.len() != len {
+        problems.push(format!("Expected {len} parts but found {}", parts.len()));
+    }
+
+    let total: u64 = parts.iter().map(|(a, _)| a.size as u64).sum();
+    if total != size {
+        problems.push(format!("Expected {size} bytes but found {total}"));
+    }
+
+    if !deep || !problems.is_empty() {
+        return problems;
+    }
+
+    let mut hash = digest::Context::new(&digest::SHA256);
+    for (part, part_sha) in parts {
+        info!("{} {}", "Downloading".blue().bold(), part.filename);
+        match _download(http.client(), part).await {
+            Ok(buf) => {
+                if part_sha.as_ref().is_some_and(|h| *h != part_hash(&buf)) {
+                    problems.push(format!("{} is corrupt", part.filename));
+                }
+                hash.update(&buf)
+            }
+            Err(e) => {
+                problems.push(format!("Failed to download {}: {e}", part.filename));
+                return problems;
+            }
         }
+    }
 
-        let next_id = entry.next.unwrap();
-        msg = http.get_message(channel.into(), next_id.into()).await?;
-        entry = FileEntry::from_str(&msg.content)?;
+    match entry.sha256 {
+        Some(expected) => {
+            let actual = hex(hash.finish().as_ref());
+            if actual != expected {
+                problems.push(format!("Expected hash {expected} but found {actual}"));
+            }
+        }
+        None => warn!("No hash recorded for {message_id}, only the download was checked"),
     }
 
-    Ok(path)
+    problems
 }
 
-pub async fn list(token: Option<String>, channel: Option<u64>, dir: Option<PathBuf>) -> Result<()> {
-    colog::default_builder()
-        .filter(Some("serenity"), log::LevelFilter::Off)
-        .init();
-    let mut path = dir
-        .unwrap_or(dirs::config_dir().ok_or(ConfigError::NoConfigDir)?)
-        .join("distore");
-    fs::create_dir_all(&path).context("Failed to create config directory")?;
-    path.push("distore.ini");
-
-    let token = token.unwrap_or_else(|| {
-        crate::config::ConfigValue::get_current_config(&path)
-            .context("Failed to get the config file")
-            .unwrap()
-            .0
-            .inner()
-            .to_string()
-    });
-    let channel = channel.unwrap_or_else(|| {
-        crate::config::ConfigValue::get_current_config(&path)
-            .unwrap()
-            .1
-            .inner()
-            .parse()
-            .unwrap()
-    });
-
-    let http = Http::new(&token);
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
 
-    let channel = http.get_channel(channel.into()).await?.id();
+pub async fn gc(
+    yes: bool,
+    token: Option<String>,
+    channel: Option<u64>,
+    remote: Option<String>,
+    dir: Option<PathBuf>,
+) -> Result<()> {
+    _init_logger();
+    let (http, channels, settings) = _connect(token, channel, remote.as_deref(), dir)?;
+    let kept = _partial_messages(&_cache_dir(settings.cache_dir.as_deref())?)?;
 
     info!("Retrieving messages...");
+    let orphans = gc_internal(&http, &channels, &kept).await?;
 
-    let list = list_internal(channel.into(), &http).await?;
+    if orphans.is_empty() {
+        println!("No orphaned messages found");
+        return Ok(());
+    }
 
-    for entry in list {
+    let mut total = 0;
+    for msg in orphans.iter() {
+        let size: u64 = msg.attachments.iter().map(|a| a.size as u64).sum();
+        total += size;
         println!(
             "{}: {}\n    {}: {}\n    {}: {}",
             "ID".bold(),
-            entry.1,
-            "Name".bold(),
-            entry.0.name.unwrap(),
+            msg.id,
+            "Parts".bold(),
+            msg.attachments.len(),
             "Size".bold(),
-            HumanBytes(entry.0.size.unwrap())
+            HumanBytes(size)
         );
     }
-    Ok(())
-}
-
-pub(crate) async fn list_internal(channel: u64, http: &Http) -> Result<Vec<(FileEntry, u64)>> {
-    let messages = _get_messages(channel.into(), http).await?;
-    let mut out = Vec::new();
-
-    for msg in messages {
-        if !msg.author.bot {
-            continue;
-        }
 
-        if !msg
-            .content
-            .starts_with("### This message is generated by Distore. Do not edit this message.\n")
-        {
-            continue;
-        }
-        let entry = FileEntry::from_str(&msg.content)?;
-        if entry.name.is_none() {
-            continue;
+    if !yes {
+        print!(
+            "Delete {} orphaned message(s) ({})? [y/N]: ",
+            orphans.len(),
+            HumanBytes(total)
+        );
+        io::stdout().flush()?;
+        let mut input = String::new();
+        io::stdin().read_line(&mut input)?;
+        if !input.trim().eq_ignore_ascii_case("y") {
+            return Ok(());
         }
-        let name = entry.name.ok_or(anyhow!("Invalid Message"))?;
-        let size = entry.size.ok_or(anyhow!("Invalid Message"))?;
-
-        out.push((
-            FileEntry {
-                name: Some(name),
-                size: Some(size),
-                len: entry.len,
-                next: entry.next,
-            },
-            msg.id.into(),
-        ))
     }
-    Ok(out)
-}
 
-pub async fn check_update() -> Result<()> {
-    let url = "https://crates.io/api/v1/crates/distore";
+    let amount = orphans.len();
+    let mut deletions = stream::iter(orphans)
+        .map(|msg| {
+            let http = &http;
+            async move { msg.delete(http.client_for(&msg)).await }
+        })
+        .buffer_unordered(http.concurrency());
 
-    let res = Client::new()
-        .get(url)
-        .header("Content-Type", "application/json")
-        .header("Accept", "application/json")
-        .header("User-Agent", format!("distore/{}", *VERSION))
-        .send()
-        .await
-        .context("Failed to fetch the latest version")?;
+    while let Some(res) = deletions.next().await {
+        res?;
+    }
 
-    let error = res.error_for_status();
-    match error {
-        Ok(res) => {
-            let json = res.json::<Value>().await?;
+    println!(
+        "{} {amount} message(s), freed {}",
+        "Deleted".green().bold(),
+        HumanBytes(total)
+    );
+    Ok(())
+}
 
-            let latest = Version::from_str(
-                json["crate"]["newest_version"]
-                    .to_string()
-                    .replace("\"", "")
-                    .as_str(),
-            )?;
+/// Returns the messages of parts sent by the bot that no valid manifest chain
+/// reaches, such as the parts left behind by a failed upload. Messages younger
+/// than [`GC_MIN_AGE`] are skipped, and so are those in `kept`, which hold the
+/// parts of failed uploads that are continued later. Every channel entries are
+/// striped across must be given, or their parts would be seen as orphaned.
+pub(crate) async fn gc_internal(
+    http: &HttpPool,
+    channels: &[u64],
+    kept: &HashSet<u64>,
+) -> Result<Vec<Message>> {
+    let mut bots = http.bot_ids();
+    if bots.is_empty() {
+        bots.push(http.get_current_user().await?.id.get());
+    }
 
-            if latest > *VERSION {
-                println!("New version available: v{} -> v{latest}", *VERSION);
-                println!("Run this command to update: cargo install distore");
-            } else {
-                println!("Already at the latest version: v{latest}");
+    let mut messages: Vec<Message> = Vec::new();
+    for channel in channels {
+        // Forum channels hold nothing but posts, and parts of uploads made
+        // with threads are in the threads of the channel
+        let mut channel_messages = Vec::new();
+        match http.get_channel((*channel).into()).await? {
+            Channel::Guild(c) => {
+                if c.kind != ChannelType::Forum {
+                    channel_messages = _get_messages(c.id, http).await?;
+                }
+                for thread in _get_threads(&c, http).await? {
+                    channel_messages.extend(_get_messages(thread.id, http).await?);
+                }
             }
-            Ok(())
+            _ => channel_messages = _get_messages((*channel).into(), http).await?,
         }
-        Err(e) => Err(anyhow::Error::new(e).context("Failed to fetch the latest version")),
+        messages.extend(
+            channel_messages
+                .into_iter()
+                .filter(|m| bots.contains(&m.author.id.get())),
+        );
     }
-}
-
-pub async fn delete(
-    message_id: u64,
-    token: Option<String>,
-    channel: Option<u64>,
-    dir: Option<PathBuf>,
-) -> Result<()> {
-    colog::default_builder()
-        .filter(Some("serenity"), log::LevelFilter::Off)
-        .init();
-    let mut path = dir
-        .unwrap_or(dirs::config_dir().ok_or(ConfigError::NoConfigDir)?)
-        .join("distore");
-    fs::create_dir_all(&path).context("Failed to create config directory")?;
-    path.push("distore.ini");
-
-    let token = token.unwrap_or_else(|| {
-        crate::config::ConfigValue::get_current_config(&path)
-            .context("Failed to get the config file")
-            .unwrap()
-            .0
-            .inner()
-            .to_string()
-    });
-    let channel = channel.unwrap_or_else(|| {
-        crate::config::ConfigValue::get_current_config(&path)
-            .unwrap()
-            .1
-            .inner()
-            .parse()
-            .unwrap()
-    });
-
-    let http = Http::new(&token);
 
-    delete_internal(&http, message_id, channel, || {}).await
-}
-
-pub(crate) async fn delete_internal<F: Fn()>(
-    http: &Http,
-    message_id: u64,
-    channel_id: u64,
-    callback: F,
-) -> Result<()> {
-    let msg = http
-        .get_message(channel_id.into(), message_id.into())
-        .await?;
+    let by_id: HashMap<u64, &Message> = messages.iter().map(|m| (m.id.get(), m)).collect();
+    let mut reachable = HashSet::new();
 
-    let mut entry = FileEntry::from_str(&msg.content)?;
+    for msg in messages.iter() {
+        if !msg.content.starts_with(MANIFEST_HEADER) {
+            continue;
+        }
+        let entry = match FileEntry::from_str(&msg.content) {
+            Ok(v) if v.name.is_some() && v.len.is_some() => v,
+            _ => continue,
+        };
 
-    let len = entry.len.ok_or(anyhow!("Invalid Message"))?;
-    info!("Deleting {} message(s)...", (len + 9) / 10);
+        reachable.insert(msg.id.get());
+        reachable.extend(entry.parts.iter().map(|p| p.id));
+        reachable.extend(entry.index);
+        reachable.extend(entry.parity_messages.iter().map(|p| p.id));
 
-    msg.delete(&http).await?;
+        let mut next = entry.next;
+        while let Some(id) = next {
+            if !reachable.insert(id) {
+                break;
+            }
+            next = by_id
+                .get(&id)
+                .and_then(|m| FileEntry::from_str(&m.content).ok())
+                .and_then(|e| e.next);
+        }
+    }
 
-    while entry.next.is_some() {
-        let msg = http
-            .get_message(channel_id.into(), entry.next.unwrap().into())
-            .await?;
-        entry = FileEntry::from_str(&msg.content)?;
-        msg.delete(&http).await?;
+    let now = Timestamp::now().unix_timestamp();
+    Ok(messages
+        .into_iter()
+        .filter(|m| !reachable.contains(&m.id.get()) && !kept.contains(&m.id.get()))
+        .filter(|m| now - m.timestamp.unix_timestamp() >= GC_MIN_AGE)
+        .filter(|m| {
+            !m.attachments.is_empty() && m.attachments.iter().all(|a| _is_part(&a.filename))
+        })
+        .collect())
+}
 
-        callback();
+/// Returns the messages recorded by the failed uploads kept in `cache_dir`.
+/// Records that can't be read are skipped with a warning.
+fn _partial_messages(cache_dir: &Path) -> Result<HashSet<u64>> {
+    let mut kept = HashSet::new();
+    for file in fs::read_dir(cache_dir)? {
+        let path = file?.path();
+        if path.extension() != Some("partial".as_ref()) {
+            continue;
+        }
+        let record = fs::read_to_string(&path)
+            .map_err(DistoreError::from)
+            .and_then(|s| Ok(FileEntry::from_str(&s)?));
+        match record {
+            Ok(record) => kept.extend(record.parts.iter().map(|p| p.id)),
+            Err(e) => warn!("Skipping {}: {e}", path.display()),
+        }
     }
+    Ok(kept)
+}
 
-    Ok(())
+/// Returns whether `filename` is named like a part, parity part or part index.
+fn _is_part(filename: &str) -> bool {
+    filename.ends_with(".index")
+        || [".part", ".parity"].iter().any(|suffix| {
+            filename
+                .rsplit_once(suffix)
+                .is_some_and(|(_, n)| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+        })
 }
 
 async fn _get_messages(
@@ -711,8 +3758,8 @@ async fn _get_messages(
 
     loop {
         let mut filter = GetMessages::new().limit(100);
-        if last_message_id.is_some() {
-            filter = filter.before(last_message_id.unwrap());
+        if let Some(id) = last_message_id {
+            filter = filter.before(id);
         }
         let messages = channel_id.messages(http, filter).await?;
 
@@ -720,9 +3767,417 @@ async fn _get_messages(
             break;
         }
 
+        last_message_id = messages.last().map(|m| m.id);
         out.extend(messages);
-        last_message_id = Some(out.last().unwrap().id);
     }
 
     Ok(out)
 }
+
+/// Fetches every guild the bot is in, a page at a time.
+async fn _get_guilds(http: &Http) -> Result<Vec<GuildInfo>, serenity::Error> {
+    const PAGE: u64 = 200;
+    let mut out: Vec<GuildInfo> = Vec::new();
+
+    loop {
+        let after = out.last().map(|g| GuildPagination::After(g.id));
+        let guilds = http.get_guilds(after, Some(PAGE)).await?;
+        let done = (guilds.len() as u64) < PAGE;
+        out.extend(guilds);
+        if done {
+            break;
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        fake_discord,
+        testing::{pool, run, scratch},
+    };
+
+    #[test]
+    fn assemble_orders_parts_by_number() {
+        let dir = scratch("assemble");
+        for i in 0..12 {
+            fs::write(dir.join(format!("notes.txt.part{i}")), [i as u8]).unwrap();
+        }
+        fs::write(dir.join("notes.txt.partial"), "not a part").unwrap();
+
+        assemble("notes.txt".into(), dir.clone(), None).unwrap();
+        assert_eq!(
+            fs::read(dir.join("notes.txt")).unwrap(),
+            (0..12).collect::<Vec<u8>>()
+        );
+
+        // A gap in the numbers is reported instead of skipped over
+        fs::remove_file(dir.join("notes.txt.part5")).unwrap();
+        let res = assemble("notes.txt".into(), dir.clone(), None);
+        assert!(matches!(res, Err(DistoreError::Integrity(_))));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn upload_resumes_kept_parts() {
+        let dir = scratch("resume");
+        let file = dir.join("notes.txt");
+        fs::write(&file, [7; 1100]).unwrap();
+        let options = UploadOptions {
+            keep_partial: true,
+            part_size: Some(100),
+            cache_dir: Some(dir.join("cache")),
+            ..Default::default()
+        };
+
+        // The first message is sent, the one with the 11th part fails
+        let first = Arc::new(Mutex::new(Vec::new()));
+        let first_ = first.clone();
+        let base = fake_discord::start(move |request| {
+            let body = String::from_utf8_lossy(&request.body);
+            match (request.method.as_str(), body.contains("notes.txt.part0\"")) {
+                ("POST", true) => {
+                    let mut first = first_.lock().unwrap();
+                    *first = fake_discord::attachments(&request.body, 100);
+                    fake_discord::message(2, "", &first).to_string()
+                }
+                _ => "{}".into(),
+            }
+        })
+        .unwrap();
+        let res = run(upload_internal(
+            &pool(base),
+            file.clone(),
+            &[10],
+            &options,
+            |_, _| {},
+        ));
+        assert!(res.is_err());
+        assert!(dir.join("cache/notes.txt.partial").exists());
+
+        // Only the 11th part is sent again
+        let first = first.lock().unwrap().clone();
+        let mut second = Vec::new();
+        let base = fake_discord::start(move |request| {
+            let body = String::from_utf8_lossy(&request.body);
+            let response = match request.method.as_str() {
+                "GET" => fake_discord::message(2, "", &first),
+                "POST" => {
+                    assert!(!body.contains("notes.txt.part0\""));
+                    second = fake_discord::attachments(&request.body, 100);
+                    fake_discord::message(3, "", &second)
+                }
+                _ => {
+                    let edit: Value = serde_json::from_slice(&request.body).unwrap_or_default();
+                    let content = edit["content"].as_str().unwrap_or_default();
+                    match request.path.ends_with("/2") {
+                        true => fake_discord::message(2, content, &first),
+                        false => fake_discord::message(3, content, &second),
+                    }
+                }
+            };
+            response.to_string()
+        })
+        .unwrap();
+        let messages = run(upload_internal(
+            &pool(base),
+            file,
+            &[10],
+            &options,
+            |_, _| {},
+        ))
+        .unwrap();
+
+        let head = FileEntry::from_str(&messages[0].content).unwrap();
+        assert_eq!(messages[0].id.get(), 2);
+        assert_eq!(head.next, Some(3));
+        assert!(!dir.join("cache/notes.txt.partial").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn failed_thread_upload_deletes_the_thread() {
+        let dir = scratch("thread-rollback");
+        let file = dir.join("notes.txt");
+        fs::write(&file, [7; 1100]).unwrap();
+        let options = UploadOptions {
+            thread: true,
+            part_size: Some(100),
+            cache_dir: Some(dir.join("cache")),
+            ..Default::default()
+        };
+
+        // A text channel starts a thread from message 2, a forum channel
+        // starts post 7. The second message, sent to the thread, fails
+        for (kind, thread) in [(0, 2), (15, 7)] {
+            let requests = Arc::new(Mutex::new(Vec::new()));
+            let requests_ = requests.clone();
+            let base = fake_discord::start(move |request| {
+                let path = request.path.clone();
+                requests_
+                    .lock()
+                    .unwrap()
+                    .push(format!("{} {path}", request.method));
+                let channel = |id: u64| {
+                    json!({"id": id.to_string(), "type": 11, "guild_id": "1",
+                        "parent_id": "10", "name": "notes.txt"})
+                };
+                let response = match (request.method.as_str(), path.as_str()) {
+                    ("GET", "/api/v10/channels/10") => {
+                        json!({"id": "10", "type": kind, "guild_id": "1", "name": "files"})
+                    }
+                    ("POST", "/api/v10/channels/10/messages") => {
+                        let attachments = fake_discord::attachments(&request.body, 100);
+                        fake_discord::message(2, "", &attachments)
+                    }
+                    ("POST", "/api/v10/channels/10/messages/2/threads") => channel(2),
+                    ("POST", "/api/v10/channels/10/threads") => channel(7),
+                    ("GET", "/api/v10/channels/7/messages/7") => fake_discord::message(7, "", &[]),
+                    _ => json!({}),
+                };
+                response.to_string()
+            })
+            .unwrap();
+
+            let res = run(upload_internal(
+                &pool(base),
+                file.clone(),
+                &[10],
+                &options,
+                |_, _| {},
+            ));
+
+            assert!(res.is_err());
+            let requests = requests.lock().unwrap();
+            let deleted = format!("DELETE /api/v10/channels/{thread}");
+            assert!(requests.contains(&deleted), "{requests:?}");
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn copy_rejects_forum_targets() {
+        let base = fake_discord::start(|request| match request.path.as_str() {
+            "/api/v10/channels/20" => {
+                json!({"id": "20", "type": 15, "guild_id": "1", "name": "posts"}).to_string()
+            }
+            _ => "{}".into(),
+        })
+        .unwrap();
+
+        let res = run(copy_internal(&pool(base), 2, 10, 20));
+
+        assert!(matches!(res, Err(DistoreError::ForumTarget(20))));
+    }
+
+    #[test]
+    fn list_reads_threads_of_text_channels() {
+        let manifest = |name: &str| {
+            FileEntry {
+                name: Some(name.into()),
+                size: Some(1000),
+                ..Default::default()
+            }
+            .to_string()
+        };
+
+        // The starter message of the thread isn't in the history that's read
+        let base = fake_discord::start(move |request| {
+            let path = request.path.split('?').next().unwrap_or_default();
+            let response = match path {
+                "/api/v10/channels/10" => {
+                    json!({"id": "10", "type": 0, "guild_id": "1", "name": "files"})
+                }
+                "/api/v10/channels/10/messages" if !request.path.contains("before") => {
+                    json!([fake_discord::message(2, &manifest("notes.txt"), &[])])
+                }
+                "/api/v10/channels/10/messages" => json!([]),
+                "/api/v10/guilds/1/threads/active" => json!({"threads": [
+                    {"id": "7", "type": 11, "guild_id": "1", "parent_id": "10",
+                        "name": "video.mp4"},
+                ], "members": []}),
+                "/api/v10/channels/10/threads/archived/public" => {
+                    json!({"threads": [], "members": [], "has_more": false})
+                }
+                "/api/v10/channels/10/messages/7" => {
+                    fake_discord::message(7, &manifest("video.mp4"), &[])
+                }
+                _ => json!({}),
+            };
+            response.to_string()
+        })
+        .unwrap();
+
+        let entries = run(list_internal(10, &pool(base))).unwrap();
+
+        let names: Vec<_> = entries
+            .iter()
+            .map(|(e, id)| (*id, e.name.as_deref().unwrap()))
+            .collect();
+        assert_eq!(names, [(2, "notes.txt"), (7, "video.mp4")]);
+    }
+
+    #[test]
+    fn gc_skips_kept_parts() {
+        let dir = scratch("gc-partial");
+        let record = FileEntry {
+            name: Some("notes.txt".into()),
+            parts: vec![PartRef {
+                id: 3,
+                offset: 0,
+                channel: Some(10),
+            }],
+            ..Default::default()
+        };
+        fs::write(dir.join("notes.txt.partial"), record.to_string()).unwrap();
+
+        let base = fake_discord::start(move |request| {
+            let path = request.path.split('?').next().unwrap_or_default();
+            let part = |id: u64| {
+                let attachment = json!({"id": id.to_string(), "filename": "notes.txt.part0",
+                    "size": 3, "url": "", "proxy_url": ""});
+                fake_discord::message(id, "", &[attachment])
+            };
+            let response = match path {
+                "/api/v10/channels/10" => {
+                    json!({"id": "10", "type": 0, "guild_id": "1", "name": "files"})
+                }
+                "/api/v10/channels/10/messages" if !request.path.contains("before") => {
+                    json!([part(4), part(3)])
+                }
+                "/api/v10/channels/10/messages" => json!([]),
+                "/api/v10/guilds/1/threads/active" => json!({"threads": [], "members": []}),
+                "/api/v10/channels/10/threads/archived/public" => {
+                    json!({"threads": [], "members": [], "has_more": false})
+                }
+                _ => json!({}),
+            };
+            response.to_string()
+        })
+        .unwrap();
+
+        let kept = _partial_messages(&dir).unwrap();
+        let orphans = run(gc_internal(&pool(base), &[10], &kept)).unwrap();
+
+        let ids: Vec<u64> = orphans.iter().map(|m| m.id.get()).collect();
+        assert_eq!(ids, [4]);
+    }
+
+    #[test]
+    fn guilds_are_read_past_the_first_page() {
+        let base = fake_discord::start(|request| {
+            let guild = |id: u64| {
+                json!({"id": id.to_string(), "name": "files", "icon": null,
+                "owner": false, "permissions": "0", "features": []})
+            };
+            let ids = match request.path.split("after=").nth(1) {
+                None => 1..201,
+                Some(after) if after.starts_with("200") => 201..202,
+                Some(_) => 0..0,
+            };
+            json!(ids.map(guild).collect::<Vec<_>>()).to_string()
+        })
+        .unwrap();
+
+        let guilds = run(_get_guilds(&pool(base))).unwrap();
+
+        assert_eq!(guilds.len(), 201);
+        assert_eq!(guilds.last().unwrap().id.get(), 201);
+    }
+
+    #[test]
+    fn download_stays_in_the_output_directory() {
+        let dir = scratch("download-name");
+        let out = dir.join("out");
+        fs::create_dir_all(&out).unwrap();
+        let head = FileEntry {
+            name: Some("../x".into()),
+            size: Some(3),
+            len: Some(1),
+            ..Default::default()
+        };
+
+        let base = fake_discord::start(move |request| match request.path.as_str() {
+            "/api/v10/channels/10/messages/2" => {
+                let url = format!("{}/part0", request.base);
+                let attachment = json!({"id": "1", "filename": "x.part0", "size": 3,
+                    "url": url, "proxy_url": url});
+                fake_discord::message(2, &head.to_string(), &[attachment]).to_string()
+            }
+            "/part0" => "abc".into(),
+            _ => "{}".into(),
+        })
+        .unwrap();
+        fs::create_dir_all(dir.join("distore")).unwrap();
+        fs::write(
+            dir.join("distore/distore.toml"),
+            format!(
+                "[global]\napi_base = {base:?}\noutput_dir = {:?}\n",
+                out.display().to_string()
+            ),
+        )
+        .unwrap();
+
+        let token = Some("NQ.x.y".into());
+        run(download(2, token, Some(10), None, Some(dir.clone()), None)).unwrap();
+
+        assert_eq!(fs::read(out.join("x")).unwrap(), b"abc");
+        assert!(!dir.join("x").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn file_names_are_single_components() {
+        assert_eq!(_file_name("a/../../x").unwrap(), PathBuf::from("x"));
+        assert_eq!(_file_name("C:\\x").unwrap(), PathBuf::from("x"));
+        assert!(_file_name("a/..").is_err());
+        assert!(_file_name("").is_err());
+
+        // Parsing decodes separators and leaves them in
+        let entry = FileEntry::from_str("v=2\nname=%2E%2E%2Fx\nsize=1").unwrap();
+        let name = entry.name.unwrap();
+        assert_eq!(name, "../x");
+        assert_eq!(_file_name(&name).unwrap(), PathBuf::from("x"));
+
+        assert!(check_name("notes.txt").is_ok());
+        for name in ["", ".", "..", "a/b", "/etc/x", "../../.bashrc", "a\\b"] {
+            assert!(check_name(name).is_err(), "{name}");
+        }
+    }
+
+    #[test]
+    fn read_range_rejects_parts_starting_late() {
+        // The first part should start at offset 0
+        let head = FileEntry {
+            name: Some("notes.txt".into()),
+            size: Some(1000),
+            len: Some(2),
+            part_size: Some(400),
+            parts: vec![PartRef {
+                id: 2,
+                offset: 400,
+                channel: None,
+            }],
+            ..Default::default()
+        };
+
+        let base = fake_discord::start(move |request| match request.path.as_str() {
+            "/api/v10/channels/10/messages/2" => {
+                fake_discord::message(2, &head.to_string(), &[]).to_string()
+            }
+            _ => "{}".into(),
+        })
+        .unwrap();
+
+        let res = run(read_range(&pool(base), 10, 2, 0, 100));
+
+        assert!(matches!(res, Err(DistoreError::UncoveredOffset(0))));
+    }
+}
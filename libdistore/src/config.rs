@@ -1,18 +1,83 @@
 use std::{
+    collections::{BTreeMap, BTreeSet},
     env::{self},
     fmt::Display,
-    fs::File,
-    io,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    iter, mem,
     path::{Path, PathBuf},
+    str::FromStr,
+    sync::Mutex,
 };
 
-use ini::{Ini, Properties};
+use ini::Ini;
+use log::warn;
+use serde::{
+    de::{self, value::MapDeserializer, IntoDeserializer, Visitor},
+    forward_to_deserialize_any, Deserialize, Deserializer, Serialize,
+};
 use thiserror::Error;
+use toml::{Table, Value};
+
+use crate::secret;
+pub use crate::secret::SecretError;
 
 #[derive(Debug, Clone)]
 pub enum ConfigValue {
     Token(String),
     Channel(String),
+    /// Any other key of [`Settings`], with its value as text
+    Setting(String, String),
+}
+
+/// Every key the config accepts. Values are typed when they're read, so a bad
+/// value is reported with its key.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// Comma separated bot tokens
+    pub token: Option<String>,
+    /// Comma separated channel IDs
+    pub channel: Option<String>,
+    /// Number of copies of every upload, counting the original
+    pub replication: Option<usize>,
+    /// Comma separated channel IDs for the extra copies
+    pub replicas: Option<String>,
+    /// Put every upload in a thread of its own
+    pub threads: Option<bool>,
+    /// Size of the parts files are split into, in bytes
+    pub part_size: Option<usize>,
+    /// Compress files before they're uploaded
+    pub compression: Option<bool>,
+    /// Number of parts transferred at once
+    pub concurrency: Option<usize>,
+    /// Directory the parts are written to before they're uploaded
+    pub cache_dir: Option<PathBuf>,
+    /// File holding the passphrase the tokens are encrypted with. Only read
+    /// from the global section
+    pub encryption_key_path: Option<PathBuf>,
+    /// Base URL of the Discord API, to go through an API proxy
+    pub api_base: Option<String>,
+    /// HTTP proxy every request goes through
+    pub proxy: Option<String>,
+    /// Directory downloads are saved in when no output is given
+    pub output_dir: Option<PathBuf>,
+}
+
+/// Layout of the config file. The INI config of older versions had a section
+/// for each of these: the general section for `global`, `[remote.<name>]` for
+/// the remotes, one named after each directory and `[encryption]`.
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ConfigFile {
+    pub(crate) global: Settings,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    remotes: BTreeMap<String, Settings>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    directories: BTreeMap<String, Settings>,
+    /// Salt of the passphrase and a value to check it with
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) encryption: BTreeMap<String, String>,
 }
 
 #[derive(Error, Debug)]
@@ -37,23 +102,395 @@ pub enum ConfigError {
 
     #[error("No channel set")]
     NoChannel,
+
+    #[error("Invalid channel ID: {0}")]
+    InvalidChannel(String),
+
+    #[error("Invalid value for {0}: {1}")]
+    InvalidValue(String, String),
+
+    #[error("Invalid value for {0}: {1} ({2})")]
+    OutOfRange(String, String, &'static str),
+
+    #[error("No remote named {0}")]
+    NoRemote(String),
+
+    #[error("Invalid config file {}: {1}", .0.display())]
+    Toml(PathBuf, toml::de::Error),
+
+    #[error("Couldn't write the config: {0}")]
+    TomlWrite(#[from] toml::ser::Error),
+
+    #[error(transparent)]
+    Secret(#[from] SecretError),
+
+    #[error("The imported tokens were encrypted with another passphrase. Export them without secrets, or set them again")]
+    ForeignSecrets,
 }
 
 type Result<T> = std::result::Result<T, ConfigError>;
 
+const REMOTE_PREFIX: &str = "remote.";
+/// Config file of older versions, in the same directory as the current one
+const LEGACY_FILE: &str = "distore.ini";
+/// Prefix of the environment variables that override config values, like
+/// `DISTORE_TOKEN`
+const ENV_PREFIX: &str = "DISTORE_";
+/// Project-local config files, looked up in the current directory and its
+/// parents
+const PROJECT_FILE: &str = ".distore.toml";
+/// Keys a project file may set. Anyone can drop a project file into a
+/// repository, so it can't pick the bot, where files are sent or where they
+/// are written to
+const PROJECT_KEYS: &[&str] = &[
+    "replication",
+    "threads",
+    "part_size",
+    "compression",
+    "concurrency",
+];
+/// Project files that were already warned about, so that each warning is only
+/// shown once
+static WARNED: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+/// Returns the path of the config file in `dir`, or in the user's config
+/// directory if it isn't given.
+pub fn config_path(dir: Option<PathBuf>) -> Result<PathBuf> {
+    Ok(dir
+        .or_else(dirs::config_dir)
+        .ok_or(ConfigError::NoConfigDir)?
+        .join("distore")
+        .join("distore.toml"))
+}
+
+/// Whether there is a config at `path`, or an INI config of an older version
+/// next to it.
+pub fn config_exists(path: &Path) -> bool {
+    path.exists() || ConfigValue::_legacy_path(path).exists()
+}
+
+/// A command line argument that may be qualified with the remote it refers
+/// to, like `work:1234` or `work:backups/db.sql`. Single letter prefixes are
+/// left alone so Windows drive letters aren't taken for remotes, and so is an
+/// argument naming a file that exists, like `notes:v2.txt`.
+#[derive(Debug, Clone)]
+pub struct RemoteArg<T> {
+    pub remote: Option<String>,
+    pub value: T,
+    /// The whole argument as a value, kept when a remote was split off.
+    literal: Option<T>,
+}
+
+impl<T> RemoteArg<T> {
+    /// Takes the argument literally if its prefix doesn't name a remote set in
+    /// the config at `path`.
+    pub fn resolve(self, path: &Path) -> Self {
+        let known = |remote: &String| ConfigValue::remotes(path).is_ok_and(|r| r.contains(remote));
+        match self.literal {
+            Some(literal) if !self.remote.as_ref().is_some_and(known) => Self {
+                remote: None,
+                value: literal,
+                literal: None,
+            },
+            _ => self,
+        }
+    }
+}
+
+impl<T: FromStr> FromStr for RemoteArg<T>
+where
+    T::Err: Display,
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (remote, value) = match s.split_once(':') {
+            Some((remote, value))
+                if remote.len() > 1
+                    && !remote.contains(['/', '\\', '.'])
+                    && !Path::new(s).exists() =>
+            {
+                (Some(remote.to_string()), value)
+            }
+            _ => (None, s),
+        };
+
+        Ok(Self {
+            literal: remote.as_ref().and_then(|_| s.parse().ok()),
+            remote,
+            value: value.parse().map_err(|e: T::Err| e.to_string())?,
+        })
+    }
+}
+
+/// Tokens are masked so they don't end up in terminal scrollback or logs.
 impl Display for ConfigValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}: {}", self._pairs().0, self.inner())
+        match self {
+            Self::Token(_) => {
+                let masked: Vec<String> = self
+                    .tokens()
+                    .iter()
+                    .map(|t| format!("{}********", t.chars().take(4).collect::<String>()))
+                    .collect();
+                write!(f, "{}: {}", self._pairs().0, masked.join(","))
+            }
+            _ => write!(f, "{}: {}", self._pairs().0, self.inner()),
+        }
+    }
+}
+
+/// Deserializes a value stored as text into the type of its key.
+struct Text<'a>(&'a str);
+
+impl<'de> IntoDeserializer<'de, de::value::Error> for Text<'_> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> Deserializer<'de> for Text<'_> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> std::result::Result<V::Value, Self::Error> {
+        visitor.visit_str(self.0)
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> std::result::Result<V::Value, Self::Error> {
+        visitor.visit_bool(self.0.trim().parse().map_err(de::Error::custom)?)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> std::result::Result<V::Value, Self::Error> {
+        visitor.visit_u64(self.0.trim().parse().map_err(de::Error::custom)?)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> std::result::Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u128 f32 f64 char str string bytes byte_buf unit
+        unit_struct newtype_struct seq tuple tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+impl Settings {
+    pub const KEYS: &'static [&'static str] = &[
+        "token",
+        "channel",
+        "replication",
+        "replicas",
+        "threads",
+        "part_size",
+        "compression",
+        "concurrency",
+        "cache_dir",
+        "encryption_key_path",
+        "api_base",
+        "proxy",
+        "output_dir",
+    ];
+
+    /// Discord doesn't take bigger uploads, even in boosted servers
+    const MAX_PART_SIZE: usize = 100 * 1000 * 1000;
+
+    /// Types and validates the values of `pairs`. An invalid value is
+    /// reported with its key.
+    pub fn from_pairs<'a>(pairs: impl IntoIterator<Item = (&'a str, &'a str)>) -> Result<Self> {
+        // Later values of a key replace earlier ones
+        pairs
+            .into_iter()
+            .try_fold(Self::default(), |settings, (key, val)| {
+                Ok(Self::_parse(key, val)?.or(settings))
+            })
+    }
+
+    /// Checks that `key` is a known key and `val` is a valid value for it.
+    pub fn check(key: &str, val: &str) -> Result<()> {
+        Self::_parse(key, val).map(drop)
+    }
+
+    /// Returns the values set in `self`, and those of `other` for the keys
+    /// `self` doesn't set.
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            token: self.token.or(other.token),
+            channel: self.channel.or(other.channel),
+            replication: self.replication.or(other.replication),
+            replicas: self.replicas.or(other.replicas),
+            threads: self.threads.or(other.threads),
+            part_size: self.part_size.or(other.part_size),
+            compression: self.compression.or(other.compression),
+            concurrency: self.concurrency.or(other.concurrency),
+            cache_dir: self.cache_dir.or(other.cache_dir),
+            encryption_key_path: self.encryption_key_path.or(other.encryption_key_path),
+            api_base: self.api_base.or(other.api_base),
+            proxy: self.proxy.or(other.proxy),
+            output_dir: self.output_dir.or(other.output_dir),
+        }
+    }
+
+    /// Types and validates a single value.
+    fn _parse(key: &str, val: &str) -> Result<Self> {
+        if !Self::KEYS.contains(&key) {
+            return Err(ConfigError::InvalidKey(key.into()));
+        }
+        let settings = Self::deserialize(MapDeserializer::new([(key, Text(val))].into_iter()))
+            .map_err(|_: de::value::Error| ConfigError::InvalidValue(key.into(), val.into()))?;
+
+        let out_of_range = |reason| Err(ConfigError::OutOfRange(key.into(), val.into(), reason));
+        if matches!(key, "channel" | "replicas") {
+            ConfigValue::Channel(val.into()).channels()?;
+        }
+        if settings.replication == Some(0) || settings.concurrency == Some(0) {
+            return out_of_range("must be at least 1");
+        }
+        if settings
+            .part_size
+            .is_some_and(|p| p == 0 || p > Self::MAX_PART_SIZE)
+        {
+            return out_of_range("must be between 1 and 100000000 bytes");
+        }
+        if [&settings.api_base, &settings.proxy]
+            .into_iter()
+            .flatten()
+            .any(|a| !a.starts_with("http://") && !a.starts_with("https://"))
+        {
+            return out_of_range("must be an http:// or https:// URL");
+        }
+
+        Ok(settings)
+    }
+
+    /// Checks the values of every key that is set, like [`Settings::check`].
+    pub fn validate(&self) -> Result<()> {
+        self.pairs()
+            .iter()
+            .try_for_each(|(key, val)| Self::check(key, val))
+    }
+
+    /// Sets `key` to `val`, typing and validating it first.
+    pub fn set(&mut self, key: &str, val: &str) -> Result<()> {
+        *self = Self::_parse(key, val)?.or(mem::take(self));
+        Ok(())
+    }
+
+    /// Returns the value of `key` as text, if it is set.
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.pairs()
+            .into_iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v)
+    }
+
+    /// Removes `key`. Returns whether it was set.
+    pub fn unset(&mut self, key: &str) -> bool {
+        match key {
+            "token" => self.token.take().is_some(),
+            "channel" => self.channel.take().is_some(),
+            "replication" => self.replication.take().is_some(),
+            "replicas" => self.replicas.take().is_some(),
+            "threads" => self.threads.take().is_some(),
+            "part_size" => self.part_size.take().is_some(),
+            "compression" => self.compression.take().is_some(),
+            "concurrency" => self.concurrency.take().is_some(),
+            "cache_dir" => self.cache_dir.take().is_some(),
+            "encryption_key_path" => self.encryption_key_path.take().is_some(),
+            "api_base" => self.api_base.take().is_some(),
+            "proxy" => self.proxy.take().is_some(),
+            "output_dir" => self.output_dir.take().is_some(),
+            _ => false,
+        }
+    }
+
+    /// Returns the keys that are set and their values as text, in the order
+    /// of [`Settings::KEYS`].
+    pub fn pairs(&self) -> Vec<(&'static str, String)> {
+        let Ok(Value::Table(table)) = Value::try_from(self) else {
+            return Vec::new();
+        };
+
+        Self::KEYS
+            .iter()
+            .filter_map(|k| {
+                table.get(*k).map(|v| match v {
+                    Value::String(s) => (*k, s.clone()),
+                    _ => (*k, v.to_string()),
+                })
+            })
+            .collect()
+    }
+}
+
+impl ConfigFile {
+    /// Returns the settings of `scope`: the global ones for `None`, those of a
+    /// remote for its [`ConfigValue::remote_section`] and those of a directory
+    /// for its path.
+    fn scope(&self, scope: Option<&str>) -> Option<&Settings> {
+        match scope {
+            None => Some(&self.global),
+            Some(s) => match s.strip_prefix(REMOTE_PREFIX) {
+                Some(remote) => self.remotes.get(remote),
+                None => self.directories.get(s),
+            },
+        }
+    }
+
+    /// Like [`ConfigFile::scope`], adding the scope if it isn't there yet.
+    fn scope_mut(&mut self, scope: Option<&str>) -> &mut Settings {
+        match scope {
+            None => &mut self.global,
+            Some(s) => match s.strip_prefix(REMOTE_PREFIX) {
+                Some(remote) => self.remotes.entry(remote.into()).or_default(),
+                None => self.directories.entry(s.into()).or_default(),
+            },
+        }
+    }
+
+    /// Returns every scope along with its settings, the global one first.
+    fn scopes(&self) -> impl Iterator<Item = (Option<String>, &Settings)> {
+        iter::once((None, &self.global))
+            .chain(
+                self.remotes
+                    .iter()
+                    .map(|(remote, s)| (Some(ConfigValue::remote_section(remote)), s)),
+            )
+            .chain(
+                self.directories
+                    .iter()
+                    .map(|(dir, s)| (Some(dir.clone()), s)),
+            )
+    }
+
+    fn settings_mut(&mut self) -> impl Iterator<Item = &mut Settings> {
+        iter::once(&mut self.global)
+            .chain(self.remotes.values_mut())
+            .chain(self.directories.values_mut())
     }
 }
 
 impl ConfigValue {
     pub fn parse<S: Into<String>>(key: S, val: S) -> Result<ConfigValue> {
-        let key = key.into();
+        let (key, val) = (key.into(), val.into());
+        Settings::check(&key, &val)?;
         match key.as_str() {
-            "token" => Ok(ConfigValue::Token(val.into())),
-            "channel" => Ok(ConfigValue::Channel(val.into())),
-            _ => Err(ConfigError::InvalidKey(key)),
+            "token" => Ok(ConfigValue::Token(val)),
+            "channel" => Ok(ConfigValue::Channel(val)),
+            _ => Ok(ConfigValue::Setting(key, val)),
         }
     }
 
@@ -61,52 +498,577 @@ impl ConfigValue {
         self._pairs().1
     }
 
+    /// Splits a comma separated list of bot tokens. Transfers spread their
+    /// requests across all of them. Empty entries are skipped.
+    pub fn tokens(&self) -> Vec<String> {
+        self.inner()
+            .split(",")
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .collect()
+    }
+
+    /// Parses a comma separated list of channel IDs. Uploads are striped
+    /// across all of them, and the first one holds the head messages.
+    pub fn channels(&self) -> Result<Vec<u64>> {
+        self.inner()
+            .split(",")
+            .map(|c| {
+                c.trim()
+                    .parse()
+                    .map_err(|_| ConfigError::InvalidChannel(c.into()))
+            })
+            .collect()
+    }
+
     fn _pairs(&self) -> (&str, &str) {
         match self {
             Self::Token(s) => ("Token", s),
             Self::Channel(s) => ("Channel", s),
+            Self::Setting(k, s) => (k, s),
         }
     }
 
+    /// Writes `value` to the `scope` section of the config. Tokens are
+    /// encrypted when `encrypt` is set, or when a passphrase was set before.
     pub fn write_to_path(
         path: &Path,
         value: &ConfigValue,
         scope: Option<impl Into<String>>,
+        encrypt: bool,
     ) -> Result<()> {
-        if !path.exists() {
-            _ = File::create(path)?;
+        let mut f = Self::_load(path)?;
+        let inner = match value {
+            Self::Token(token) if encrypt || secret::is_set_up(&f) => {
+                secret::encrypt(&secret::set_up(&mut f)?, token)?
+            }
+            _ => value.inner().to_string(),
+        };
+        let scope = scope.map(Into::into);
+        f.scope_mut(scope.as_deref())
+            .set(&value._pairs().0.to_lowercase(), &inner)?;
+        Self::_save(&f, path)
+    }
+
+    /// Resolves the token and channel a command runs with. Each of them is
+    /// taken from the first of these that sets it:
+    ///
+    /// 1. The `token` and `channel` arguments, coming from command line flags
+    /// 2. The section of `remote`, if one is given. The layers below are
+    ///    skipped then
+    /// 3. The `DISTORE_TOKEN` and `DISTORE_CHANNEL` environment variables
+    /// 4. The sections of the current directory and of its parents, and the
+    ///    `.distore.toml` files in them, the nearest directory first. Project
+    ///    files can't set the token or the channel
+    /// 5. The global section
+    ///
+    /// A missing config file counts as an empty one, so the flags or the
+    /// environment variables are enough on their own.
+    pub fn resolve(
+        path: &Path,
+        token: Option<String>,
+        channel: Option<u64>,
+        remote: Option<&str>,
+    ) -> Result<(ConfigValue, ConfigValue)> {
+        let token = match token {
+            Some(t) => ConfigValue::Token(t),
+            None => Self::get_current_value(path, "token", remote)?.ok_or(ConfigError::NoToken)?,
+        };
+        let channel = match channel {
+            Some(c) => ConfigValue::Channel(c.to_string()),
+            None => {
+                Self::get_current_value(path, "channel", remote)?.ok_or(ConfigError::NoChannel)?
+            }
+        };
+
+        Ok((token, channel))
+    }
+
+    pub fn get_current_config(
+        path: &Path,
+        remote: Option<&str>,
+    ) -> Result<(ConfigValue, ConfigValue)> {
+        Self::resolve(path, None, None, remote)
+    }
+
+    /// Returns the value of `key` from the first layer that sets it, in the
+    /// order described in [`ConfigValue::resolve`].
+    pub fn get_current_value(
+        path: &Path,
+        key: &str,
+        remote: Option<&str>,
+    ) -> Result<Option<ConfigValue>> {
+        let conf = Self::_load(path)?;
+        Self::_lookup(&conf, key, remote)?
+            .map(|val| ConfigValue::parse(key, Self::_reveal(&conf, val)?.as_str()))
+            .transpose()
+    }
+
+    /// Returns every setting, each from the first layer that sets it, in the
+    /// order described in [`ConfigValue::resolve`].
+    pub fn get_current_settings(path: &Path, remote: Option<&str>) -> Result<Settings> {
+        let conf = Self::_load(path)?;
+        let mut pairs = Vec::new();
+        for key in Settings::KEYS {
+            if let Some(val) = Self::_lookup(&conf, key, remote)? {
+                pairs.push((*key, Self::_reveal(&conf, val)?));
+            }
         }
-        let mut f = Ini::load_from_file(path)?;
-        f.with_section(scope)
-            .set(value._pairs().0.to_lowercase(), value.inner());
-        f.write_to_file(path)?;
-        Ok(())
+
+        Settings::from_pairs(pairs.iter().map(|(k, v)| (*k, v.as_str())))
+    }
+
+    /// Returns the settings set in the `scope` section alone.
+    pub fn get_scope_settings(path: &Path, scope: Option<&str>) -> Result<Settings> {
+        let conf = Self::_load(path)?;
+        let mut settings = conf.scope(scope).cloned().unwrap_or_default();
+        settings.token = settings
+            .token
+            .map(|t| Self::_reveal(&conf, t))
+            .transpose()?;
+
+        Ok(settings)
+    }
+
+    /// Removes `key` from the `scope` section. Returns whether it was set.
+    pub fn unset_from_path(path: &Path, key: &str, scope: Option<&str>) -> Result<bool> {
+        if !Settings::KEYS.contains(&key) {
+            return Err(ConfigError::InvalidKey(key.into()));
+        }
+        let mut conf = Self::_load(path)?;
+        let removed = conf.scope(scope).is_some() && conf.scope_mut(scope).unset(key);
+        if removed {
+            Self::_save(&conf, path)?;
+        }
+        Ok(removed)
+    }
+
+    /// Moves the INI config of older versions next to `path` into `path`,
+    /// rewriting the values in their typed form, like `threads = True` as
+    /// `threads = true`. Values that can't be typed are left out and returned.
+    /// The old file is kept as `distore.ini.bak`.
+    ///
+    /// Returns `None` if there's no INI config, or it was migrated already.
+    pub fn migrate(path: &Path) -> Result<Option<Vec<String>>> {
+        if path.exists() || !Self::_legacy_path(path).exists() {
+            return Ok(None);
+        }
+        let legacy = Self::_legacy_path(path);
+        Self::_restrict(&legacy)?;
+        let (conf, problems) = Self::_from_ini(&Ini::load_from_file(legacy)?);
+
+        Self::_save(&conf, path)?;
+        Ok(Some(
+            problems
+                .into_iter()
+                .map(|(name, e)| format!("[{name}] {e}"))
+                .collect(),
+        ))
+    }
+
+    /// Returns the directories that have their own section in the config,
+    /// along with the keys they set.
+    pub fn scopes(path: &Path) -> Result<Vec<(String, Vec<String>)>> {
+        let conf = Self::_load(path)?;
+        Ok(conf
+            .directories
+            .iter()
+            .map(|(dir, settings)| {
+                (
+                    dir.clone(),
+                    settings
+                        .pairs()
+                        .into_iter()
+                        .map(|(k, _)| k.into())
+                        .collect(),
+                )
+            })
+            .collect())
+    }
+
+    /// Removes the section of the directory `scope`. Returns whether there was
+    /// one.
+    pub fn remove_scope(path: &Path, scope: &str) -> Result<bool> {
+        let mut conf = Self::_load(path)?;
+        let removed = conf.directories.remove(scope).is_some();
+        if removed {
+            Self::_save(&conf, path)?;
+        }
+        Ok(removed)
+    }
+
+    /// Returns the whole config in the layout of the config file. Without
+    /// `secrets`, the tokens and the passphrase check are left out.
+    pub fn export(path: &Path, secrets: bool) -> Result<String> {
+        let mut conf = Self::_load(path)?;
+        if !secrets {
+            conf.encryption.clear();
+            conf.settings_mut().for_each(|s| s.token = None);
+        }
+
+        Ok(toml::to_string(&conf)?)
+    }
+
+    /// Merges a config exported with [`ConfigValue::export`] into the one at
+    /// `path`. Every value is checked first, so nothing is written if one of
+    /// them is invalid. Exports of older versions, in INI, are read too.
+    ///
+    /// Encrypted tokens can only be imported into a config without a
+    /// passphrase or with the same one. Plain tokens are encrypted if the
+    /// config has a passphrase.
+    pub fn import(path: &Path, data: &str) -> Result<()> {
+        let imported: ConfigFile = match toml::from_str(data) {
+            Ok(file) => file,
+            Err(e) => {
+                let ini = Ini::load_from_str(data)
+                    .map_err(|_| ConfigError::Toml(PathBuf::from("<import>"), e))?;
+                match Self::_from_ini(&ini) {
+                    (file, problems) if problems.is_empty() => file,
+                    (_, mut problems) => return Err(problems.remove(0).1),
+                }
+            }
+        };
+        imported.scopes().try_for_each(|(_, s)| s.validate())?;
+        let mut conf = Self::_load(path)?;
+
+        let encrypted = imported
+            .scopes()
+            .filter_map(|(_, s)| s.token.as_deref())
+            .any(secret::is_encrypted);
+        if encrypted {
+            match (conf.encryption.get("salt"), imported.encryption.get("salt")) {
+                (_, None) => return Err(SecretError::NotSetUp.into()),
+                (Some(ours), Some(theirs)) if ours != theirs => {
+                    return Err(ConfigError::ForeignSecrets)
+                }
+                (None, Some(_)) => conf.encryption = imported.encryption.clone(),
+                _ => (),
+            }
+        }
+
+        let mut unlocked = None;
+        for (scope, settings) in imported.scopes() {
+            let mut settings = settings.clone();
+            if let Some(token) = settings.token.as_mut() {
+                if !secret::is_encrypted(token) && secret::is_set_up(&conf) {
+                    if unlocked.is_none() {
+                        unlocked = Some(secret::unlock(&conf)?);
+                    }
+                    *token = secret::encrypt(unlocked.as_ref().unwrap(), token)?;
+                }
+            }
+
+            let current = conf.scope_mut(scope.as_deref());
+            *current = settings.or(mem::take(current));
+        }
+        Self::_save(&conf, path)
     }
 
-    pub fn get_current_config(path: &Path) -> Result<(ConfigValue, ConfigValue)> {
+    /// Returns `val` the way it's written back once typed.
+    fn _normalize(key: &str, val: &str) -> Result<String> {
+        let val = match key {
+            "threads" | "compression" => val.trim().to_lowercase(),
+            "channel" | "replicas" => val
+                .split(',')
+                .map(|c| c.trim())
+                .collect::<Vec<_>>()
+                .join(","),
+            _ => val.trim().to_string(),
+        };
+        let settings = Settings::from_pairs([(key, val.as_str())])?;
+        Ok(settings
+            .pairs()
+            .into_iter()
+            .next()
+            .map(|(_, v)| v)
+            .unwrap_or(val))
+    }
+
+    /// Returns the names of the remotes set in the config, sorted by name.
+    pub fn remotes(path: &Path) -> Result<Vec<String>> {
+        let conf = Self::_load(path)?;
+        Ok(conf.remotes.into_keys().collect())
+    }
+
+    /// Name of the section holding the settings of `remote`.
+    pub fn remote_section(remote: &str) -> String {
+        format!("{REMOTE_PREFIX}{remote}")
+    }
+
+    fn _lookup(conf: &ConfigFile, key: &str, remote: Option<&str>) -> Result<Option<String>> {
+        if let Some(remote) = remote {
+            let settings = conf
+                .remotes
+                .get(remote)
+                .ok_or(ConfigError::NoRemote(remote.into()))?;
+            return Ok(settings.get(key));
+        }
+
+        let var = format!("{ENV_PREFIX}{}", key.to_uppercase());
+        if let Some(val) = env::var(var).ok().filter(|v| !v.is_empty()) {
+            return Ok(Some(val));
+        }
+
         let current_dir = env::current_dir()?;
-        let conf = Ini::load_from_file(path)?;
+        for dir in current_dir.ancestors() {
+            let settings = dir.to_str().and_then(|d| conf.directories.get(d));
+            if let Some(val) = settings.and_then(|s| s.get(key)) {
+                return Ok(Some(val));
+            }
+            if let Some(val) = Self::_project_value(dir, key)? {
+                return Ok(Some(val));
+            }
+        }
+
+        Ok(conf.global.get(key))
+    }
 
-        let section = match conf.section(current_dir.to_str()) {
-            Some(s) => s,
-            None => conf.general_section(),
+    /// Reads `key` from the `.distore.toml` file in `dir`, if there is one.
+    /// Lists are turned into comma separated values. Unknown keys and keys
+    /// outside of [`PROJECT_KEYS`] are ignored with a warning, so that they
+    /// don't break every command run below `dir`.
+    fn _project_value(dir: &Path, key: &str) -> Result<Option<String>> {
+        let path = dir.join(PROJECT_FILE);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let table: Table = fs::read_to_string(&path)?
+            .parse()
+            .map_err(|e| ConfigError::Toml(path.clone(), e))?;
+        if WARNED.lock().unwrap().insert(path.clone()) {
+            for k in table.keys() {
+                if !Settings::KEYS.contains(&k.as_str()) {
+                    warn!("Ignoring the unknown key {k} in {}", path.display());
+                } else if !PROJECT_KEYS.contains(&k.as_str()) {
+                    warn!(
+                        "Ignoring {k} in {}, set it with `distore config` instead",
+                        path.display()
+                    );
+                }
+            }
+        }
+        if !PROJECT_KEYS.contains(&key) {
+            return Ok(None);
+        }
+
+        let invalid = |v: &Value| ConfigError::InvalidValue(key.into(), v.to_string());
+        let scalar = |v: &Value| match v {
+            Value::String(s) => Ok(s.clone()),
+            Value::Integer(i) => Ok(i.to_string()),
+            Value::Boolean(b) => Ok(b.to_string()),
+            _ => Err(invalid(v)),
         };
 
-        Self::_get_config(section)
+        table
+            .get(key)
+            .map(|v| match v {
+                Value::Array(a) => Ok(a.iter().map(scalar).collect::<Result<Vec<_>>>()?.join(",")),
+                _ => scalar(v),
+            })
+            .transpose()
     }
 
-    pub fn get_global_config(path: &Path) -> Result<(ConfigValue, ConfigValue)> {
-        let conf = Ini::load_from_file(path)?;
-        Self::_get_config(conf.general_section())
+    /// Decrypts `val` if it was stored encrypted.
+    fn _reveal(conf: &ConfigFile, val: String) -> Result<String> {
+        match secret::is_encrypted(&val) {
+            true => Ok(secret::decrypt(&secret::unlock(conf)?, &val)?),
+            false => Ok(val),
+        }
+    }
+
+    /// Loads the config file, or the INI config of older versions if it
+    /// wasn't migrated yet. A missing config counts as an empty one.
+    fn _load(path: &Path) -> Result<ConfigFile> {
+        let legacy = Self::_legacy_path(path);
+        if path.exists() {
+            Self::_restrict(path)?;
+            let file: ConfigFile = toml::from_str(&fs::read_to_string(path)?)
+                .map_err(|e| ConfigError::Toml(path.into(), e))?;
+            file.scopes().try_for_each(|(_, s)| s.validate())?;
+            Ok(file)
+        } else if legacy.exists() {
+            Self::_restrict(&legacy)?;
+            let (file, problems) = Self::_from_ini(&Ini::load_from_file(&legacy)?);
+            for (name, e) in problems {
+                warn!("Ignoring [{name}] in {}: {e}", legacy.display());
+            }
+            Ok(file)
+        } else {
+            Ok(ConfigFile::default())
+        }
     }
 
-    fn _get_config(section: &Properties) -> Result<(ConfigValue, ConfigValue)> {
-        let token = section.get("token").ok_or(ConfigError::NoToken)?;
-        let channel = section.get("channel").ok_or(ConfigError::NoChannel)?;
+    /// Writes `conf` to `path`. A new file is created readable only by the
+    /// owner from the start. An INI config of an older version is moved out
+    /// of the way, as `conf` holds all of it.
+    fn _save(conf: &ConfigFile, path: &Path) -> Result<()> {
+        let data = toml::to_string(conf)?;
+
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(path)?.write_all(data.as_bytes())?;
+
+        let legacy = Self::_legacy_path(path);
+        if legacy.exists() {
+            let backup = legacy.with_extension("ini.bak");
+            fs::rename(&legacy, &backup)?;
+            warn!(
+                "Moved the config to {}, the old one is kept as {}",
+                path.display(),
+                backup.display()
+            );
+        }
+        Ok(())
+    }
+
+    fn _legacy_path(path: &Path) -> PathBuf {
+        path.with_file_name(LEGACY_FILE)
+    }
+
+    /// Types the sections of an INI config of an older version into the
+    /// layout of the config file, rewriting values like `threads = True` as
+    /// `threads = true`. Values that can't be typed are left out, and
+    /// returned with the name of their section.
+    fn _from_ini(ini: &Ini) -> (ConfigFile, Vec<(String, ConfigError)>) {
+        let mut file = ConfigFile::default();
+        let mut problems = Vec::new();
+        for (section, props) in ini.iter() {
+            if section == Some(secret::SECTION) {
+                file.encryption = props
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect();
+                continue;
+            }
+
+            let settings = file.scope_mut(section);
+            for (key, val) in props.iter() {
+                let typed = match key {
+                    // Tokens may be encrypted, and are kept as they are
+                    "token" => Ok(val.to_string()),
+                    _ => Self::_normalize(key, val),
+                };
+                if let Err(e) = typed.and_then(|v| settings.set(key, &v)) {
+                    problems.push((section.unwrap_or("global").to_string(), e));
+                }
+            }
+        }
+        (file, problems)
+    }
+
+    /// Makes sure only the owner can read the config file, since it holds the
+    /// tokens of the bots.
+    #[cfg(unix)]
+    fn _restrict(path: &Path) -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut permissions = fs::metadata(path)?.permissions();
+        if permissions.mode() & 0o077 != 0 {
+            permissions.set_mode(0o600);
+            fs::set_permissions(path, permissions)?;
+            warn!("Made {} readable only by you", path.display());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn _restrict(_: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn get_global_config(path: &Path) -> Result<(ConfigValue, ConfigValue)> {
+        let conf = Self::_load(path)?;
+        let token = conf.global.token.clone().ok_or(ConfigError::NoToken)?;
+        let channel = conf
+            .global
+            .channel
+            .as_deref()
+            .ok_or(ConfigError::NoChannel)?;
 
         Ok((
-            ConfigValue::parse("token", token)?,
+            ConfigValue::parse("token", Self::_reveal(&conf, token)?.as_str())?,
             ConfigValue::parse("channel", channel)?,
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::scratch;
+
+    #[test]
+    fn round_trip() {
+        let dir = scratch("round-trip");
+        let path = dir.join("distore.toml");
+        let write = |key, val, scope: Option<&str>| {
+            let value = ConfigValue::parse(key, val).unwrap();
+            ConfigValue::write_to_path(&path, &value, scope, false).unwrap();
+        };
+        write("token", "NQ.x.y", None);
+        write("threads", "true", None);
+        write("channel", "12", Some("remote.work"));
+        write("part_size", "5000", Some("/tmp/project"));
+
+        let file: ConfigFile = toml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(file.global.threads, Some(true));
+        assert_eq!(file.remotes["work"].channel.as_deref(), Some("12"));
+        assert_eq!(file.directories["/tmp/project"].part_size, Some(5000));
+
+        assert!(ConfigValue::unset_from_path(&path, "threads", None).unwrap());
+        assert!(!ConfigValue::unset_from_path(&path, "threads", Some("remote.none")).unwrap());
+        let global = ConfigValue::get_scope_settings(&path, None).unwrap();
+        assert_eq!(global.token.as_deref(), Some("NQ.x.y"));
+        assert_eq!(global.threads, None);
+        assert_eq!(ConfigValue::remotes(&path).unwrap(), ["work"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_value_names_its_key() {
+        let err = Settings::from_pairs([("threads", "true"), ("concurrency", "abc")]).unwrap_err();
+        assert!(matches!(err, ConfigError::InvalidValue(key, _) if key == "concurrency"));
+    }
+
+    #[test]
+    fn migrate() {
+        let dir = scratch("migrate");
+        let path = dir.join("distore.toml");
+        fs::write(
+            dir.join(LEGACY_FILE),
+            "token=NQ.x.y\nthreads=True\ncolour=red\n\n[remote.work]\nchannel=12, 13\n",
+        )
+        .unwrap();
+
+        let problems = ConfigValue::migrate(&path).unwrap().unwrap();
+        assert_eq!(problems, ["[global] Invalid key: colour"]);
+        assert!(dir.join("distore.ini.bak").exists());
+        assert!(!dir.join(LEGACY_FILE).exists());
+
+        let settings = ConfigValue::get_scope_settings(&path, None).unwrap();
+        assert_eq!(settings.threads, Some(true));
+        let work = ConfigValue::get_scope_settings(&path, Some("remote.work")).unwrap();
+        assert_eq!(work.channel.as_deref(), Some("12,13"));
+
+        assert!(ConfigValue::migrate(&path).unwrap().is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn project_file_ignores_forbidden_keys() {
+        let dir = scratch("project");
+        fs::write(
+            dir.join(PROJECT_FILE),
+            "token = \"NQ.x.y\"\ncolour = \"red\"\npart_size = 5000\n",
+        )
+        .unwrap();
+
+        let value = |key| ConfigValue::_project_value(&dir, key).unwrap();
+        assert_eq!(value("part_size").as_deref(), Some("5000"));
+        assert_eq!(value("token"), None);
+        assert_eq!(value("channel"), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,299 @@
+use thiserror::Error;
+
+/// Reed-Solomon erasure code over GF(2^8). Any `data` of the `data + parity`
+/// shards are enough to rebuild the others. Parity rows come from a Cauchy
+/// matrix, so every square submatrix of the encoding matrix is invertible.
+pub(crate) struct ReedSolomon {
+    data: usize,
+    parity: usize,
+}
+
+#[derive(Error, Debug)]
+pub enum ErasureError {
+    #[error("At most 256 data and parity shards are supported, got {0}")]
+    TooManyShards(usize),
+
+    #[error("Need {needed} shards to rebuild the data but only {found} are intact")]
+    TooFewShards { needed: usize, found: usize },
+
+    #[error("Shards must all have the same length")]
+    ShardLength,
+}
+
+const TABLES: ([u8; 512], [u8; 256]) = tables();
+const EXP: [u8; 512] = TABLES.0;
+const LOG: [u8; 256] = TABLES.1;
+
+/// Exponent and logarithm tables for the field with the 0x11d polynomial. The
+/// exponent table is doubled so sums of two logarithms don't need a modulo.
+const fn tables() -> ([u8; 512], [u8; 256]) {
+    let mut exp = [0; 512];
+    let mut log = [0; 256];
+
+    let mut x: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        exp[i] = x as u8;
+        log[x as usize] = i as u8;
+        x <<= 1;
+        if x & 0x100 != 0 {
+            x ^= 0x11d;
+        }
+        i += 1;
+    }
+    while i < 512 {
+        exp[i] = exp[i - 255];
+        i += 1;
+    }
+
+    (exp, log)
+}
+
+fn mul(a: u8, b: u8) -> u8 {
+    match a == 0 || b == 0 {
+        true => 0,
+        false => EXP[LOG[a as usize] as usize + LOG[b as usize] as usize],
+    }
+}
+
+fn inv(a: u8) -> u8 {
+    EXP[255 - LOG[a as usize] as usize]
+}
+
+/// Adds `coefficient * src` to `dst`.
+fn mul_add(dst: &mut [u8], src: &[u8], coefficient: u8) {
+    if coefficient == 0 {
+        return;
+    }
+    let row: Vec<u8> = (0..=255).map(|b| mul(coefficient, b)).collect();
+    for (d, s) in dst.iter_mut().zip(src) {
+        *d ^= row[*s as usize];
+    }
+}
+
+impl ReedSolomon {
+    pub fn new(data: usize, parity: usize) -> Result<Self, ErasureError> {
+        if data + parity > 256 {
+            return Err(ErasureError::TooManyShards(data + parity));
+        }
+        Ok(Self { data, parity })
+    }
+
+    /// Entry of the encoding matrix. The first `data` rows are the identity,
+    /// and parity row `row` is `1 / (row + col)` with addition being XOR.
+    fn coefficient(&self, row: usize, col: usize) -> u8 {
+        match row < self.data {
+            true => (row == col) as u8,
+            false => inv((row ^ col) as u8),
+        }
+    }
+
+    /// Computes the parity shards of `data`, which must all have the same length.
+    pub fn encode(&self, data: &[&[u8]]) -> Result<Vec<Vec<u8>>, ErasureError> {
+        let len = data.first().map_or(0, |s| s.len());
+        if data.len() != self.data || data.iter().any(|s| s.len() != len) {
+            return Err(ErasureError::ShardLength);
+        }
+
+        Ok((0..self.parity)
+            .map(|p| {
+                let mut out = vec![0; len];
+                for (col, shard) in data.iter().enumerate() {
+                    mul_add(&mut out, shard, self.coefficient(self.data + p, col));
+                }
+                out
+            })
+            .collect())
+    }
+
+    /// Fills in every missing shard. `shards` holds the data shards followed
+    /// by the parity shards, with `None` for the ones that were lost.
+    pub fn reconstruct(&self, shards: &mut [Option<Vec<u8>>]) -> Result<(), ErasureError> {
+        let present: Vec<usize> = (0..shards.len()).filter(|i| shards[*i].is_some()).collect();
+        if present.len() < self.data {
+            return Err(ErasureError::TooFewShards {
+                needed: self.data,
+                found: present.len(),
+            });
+        }
+        let len = shards[present[0]].as_ref().unwrap().len();
+        if present
+            .iter()
+            .any(|i| shards[*i].as_ref().unwrap().len() != len)
+        {
+            return Err(ErasureError::ShardLength);
+        }
+
+        // Invert the rows of the shards we have to get back to the data
+        let rows = &present[..self.data];
+        let matrix: Vec<Vec<u8>> = rows
+            .iter()
+            .map(|r| (0..self.data).map(|c| self.coefficient(*r, c)).collect())
+            .collect();
+        let decode = invert(matrix);
+
+        for i in 0..self.data {
+            if shards[i].is_some() {
+                continue;
+            }
+            let mut out = vec![0; len];
+            for (j, row) in rows.iter().enumerate() {
+                mul_add(&mut out, shards[*row].as_ref().unwrap(), decode[i][j]);
+            }
+            shards[i] = Some(out);
+        }
+
+        for p in 0..self.parity {
+            if shards[self.data + p].is_some() {
+                continue;
+            }
+            let mut out = vec![0; len];
+            for (col, shard) in shards[..self.data].iter().enumerate() {
+                let coefficient = self.coefficient(self.data + p, col);
+                mul_add(&mut out, shard.as_ref().unwrap(), coefficient);
+            }
+            shards[self.data + p] = Some(out);
+        }
+
+        Ok(())
+    }
+}
+
+/// Gauss-Jordan elimination. The matrix is always invertible since it is made
+/// of rows of the identity and of a Cauchy matrix.
+fn invert(mut matrix: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    let n = matrix.len();
+    let mut out: Vec<Vec<u8>> = (0..n)
+        .map(|r| (0..n).map(|c| (r == c) as u8).collect())
+        .collect();
+
+    for col in 0..n {
+        let pivot = (col..n).find(|r| matrix[*r][col] != 0).unwrap();
+        matrix.swap(col, pivot);
+        out.swap(col, pivot);
+
+        let scale = inv(matrix[col][col]);
+        for c in 0..n {
+            matrix[col][c] = mul(matrix[col][c], scale);
+            out[col][c] = mul(out[col][c], scale);
+        }
+
+        for row in 0..n {
+            let factor = matrix[row][col];
+            if row == col || factor == 0 {
+                continue;
+            }
+            for c in 0..n {
+                matrix[row][c] ^= mul(factor, matrix[col][c]);
+                out[row][c] ^= mul(factor, out[col][c]);
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shards(data: usize, len: usize) -> Vec<Vec<u8>> {
+        (0..data)
+            .map(|s| (0..len).map(|i| (s * 31 + i * 7) as u8).collect())
+            .collect()
+    }
+
+    fn encode(codec: &ReedSolomon, data: &[Vec<u8>]) -> Vec<Option<Vec<u8>>> {
+        let refs: Vec<&[u8]> = data.iter().map(Vec::as_slice).collect();
+        let parity = codec.encode(&refs).unwrap();
+        data.iter().cloned().chain(parity).map(Some).collect()
+    }
+
+    #[test]
+    fn field_inverse() {
+        for a in 1..=255 {
+            assert_eq!(mul(a, inv(a)), 1, "{a}");
+        }
+        assert_eq!(mul(0, 7), 0);
+    }
+
+    #[test]
+    fn invert_identity() {
+        let codec = ReedSolomon::new(4, 3).unwrap();
+        let rows = [1, 4, 5, 6];
+        let matrix: Vec<Vec<u8>> = rows
+            .iter()
+            .map(|r| (0..4).map(|c| codec.coefficient(*r, c)).collect())
+            .collect();
+        let inverse = invert(matrix.clone());
+
+        for (r, row) in matrix.iter().enumerate() {
+            for c in 0..4 {
+                let dot = row
+                    .iter()
+                    .zip(&inverse)
+                    .fold(0, |acc, (a, b)| acc ^ mul(*a, b[c]));
+                assert_eq!(dot, (r == c) as u8);
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let codec = ReedSolomon::new(5, 2).unwrap();
+        let data = shards(5, 100);
+        let mut all = encode(&codec, &data);
+        let expected = all.clone();
+
+        codec.reconstruct(&mut all).unwrap();
+        assert_eq!(all, expected);
+    }
+
+    #[test]
+    fn lose_parity_shards() {
+        for (data_len, parity) in [(10, 2), (3, 3), (1, 1), (7, 4)] {
+            let codec = ReedSolomon::new(data_len, parity).unwrap();
+            let expected = encode(&codec, &shards(data_len, 64));
+
+            // Lose every window of `parity` shards, data and parity alike
+            for start in 0..=data_len {
+                let mut lost = expected.clone();
+                for shard in lost.iter_mut().skip(start).take(parity) {
+                    *shard = None;
+                }
+                codec.reconstruct(&mut lost).unwrap();
+                assert_eq!(lost, expected, "{data_len}+{parity}, lost from {start}");
+            }
+        }
+    }
+
+    #[test]
+    fn too_many_lost() {
+        let codec = ReedSolomon::new(4, 2).unwrap();
+        let mut all = encode(&codec, &shards(4, 16));
+        all[0] = None;
+        all[2] = None;
+        all[5] = None;
+
+        assert!(matches!(
+            codec.reconstruct(&mut all),
+            Err(ErasureError::TooFewShards {
+                needed: 4,
+                found: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn shard_lengths() {
+        let codec = ReedSolomon::new(2, 1).unwrap();
+        assert!(matches!(
+            codec.encode(&[&[1, 2], &[3]]),
+            Err(ErasureError::ShardLength)
+        ));
+        assert!(matches!(
+            ReedSolomon::new(200, 57),
+            Err(ErasureError::TooManyShards(257))
+        ));
+    }
+}
@@ -0,0 +1,190 @@
+use std::{io, path::PathBuf};
+
+use serenity::http::HttpError;
+use thiserror::Error;
+
+use crate::{config::ConfigError, erasure::ErasureError, parser::ParseError};
+
+#[derive(Error, Debug)]
+pub enum DistoreError {
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+
+    /// The message isn't the head of an entry
+    #[error("Message {0} isn't a file uploaded by distore")]
+    NotAManifest(u64),
+
+    /// The head message lacks something every entry records
+    #[error("Invalid manifest: no {0}")]
+    IncompleteManifest(&'static str),
+
+    /// The parts listed in the manifest start after the given offset
+    #[error("Invalid manifest: no part covers byte {0}")]
+    UncoveredOffset(u64),
+
+    /// A request couldn't be sent or its response couldn't be read
+    #[error("Network error: {0}")]
+    Network(#[source] Box<dyn std::error::Error + Send + Sync>),
+
+    /// Discord refused a request, like when the bot lacks a permission or is
+    /// rate limited
+    #[error("Discord API error {status}: {message}")]
+    Discord {
+        status: u16,
+        code: isize,
+        message: String,
+    },
+
+    /// Parts are missing, or what was read doesn't match its hash
+    #[error("{0}")]
+    Integrity(String),
+
+    /// The entry has no parity parts to rebuild broken parts from
+    #[error("{0} has no parity parts to repair it from")]
+    NoParity(String),
+
+    /// The entry was compressed with something this version can't read
+    #[error("Unsupported compression: {0}")]
+    UnsupportedCompression(String),
+
+    /// Compressed entries can only be read from their start
+    #[error("Compressed files can't be read in ranges")]
+    CompressedRange,
+
+    /// Even with its parts moved to an index, the manifest is longer than a
+    /// message can be
+    #[error("The manifest is too long to fit in the head message")]
+    ManifestTooLong,
+
+    #[error("{} is empty, there's nothing to upload", .0.display())]
+    EmptyFile(PathBuf),
+
+    #[error("Invalid file name: {}", .0.display())]
+    InvalidFileName(PathBuf),
+
+    #[error("File name cannot be empty")]
+    EmptyName,
+
+    /// A file name that would point outside the directory it's put in
+    #[error("Invalid file name '{0}': it can't contain '/' or '\\', or be '.' or '..'")]
+    UnsafeName(String),
+
+    #[error("Nothing was uploaded")]
+    NothingUploaded,
+
+    #[error("Uploads to a thread can't be striped across several channels")]
+    ThreadStriping,
+
+    #[error("Channel {0} is a forum channel. Files can't be copied or moved into one")]
+    ForumTarget(u64),
+
+    #[error("Replication factor {factor} needs {needed} replica channel(s) but {set} are set")]
+    MissingReplicas {
+        factor: usize,
+        needed: usize,
+        set: usize,
+    },
+
+    #[error("{0} isn't a server channel")]
+    NotAGuildChannel(u64),
+
+    /// The bot lacks permissions it needs to store files in a channel
+    #[error("The bot is missing these permissions in #{channel}: {}", .missing.join(", "))]
+    MissingPermissions {
+        channel: String,
+        missing: Vec<String>,
+    },
+
+    /// The key is known but has no value in the config
+    #[error("{0} is not set")]
+    NotSet(String),
+
+    /// No cache directory is set and the system has none either
+    #[error("No cache directory found")]
+    NoCacheDir,
+
+    /// crates.io answered with a version that couldn't be parsed
+    #[error("Invalid version on crates.io: {0}")]
+    InvalidVersion(#[from] semver::Error),
+
+    /// There's no token to check, and no terminal to ask for one in
+    #[error("No token given. Pass one with --token")]
+    NoTokenGiven,
+
+    /// The bot lacks the permissions to store files in every channel of the
+    /// server
+    #[error("The bot can't store files in any channel of {guild}. It needs these permissions: {}", .needed.join(", "))]
+    NoUsableChannel { guild: String, needed: Vec<String> },
+
+    /// The bot isn't allowed to create channels in the server
+    #[error("Couldn't create the channel. The bot needs the Manage Channels and Manage Roles permissions")]
+    CannotCreateChannel,
+
+    /// There's nothing to pick from, like when the bot isn't in any server
+    #[error("The bot can't see any {0}")]
+    NothingToPick(String),
+
+    /// There are several options to pick from and no terminal to ask in
+    #[error("The bot can see more than one {what}. Pick one with --{flag}")]
+    AmbiguousPick { what: String, flag: String },
+
+    #[error("Cancelled")]
+    Cancelled,
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    /// Boxed, as it's much larger than every other variant
+    #[error(transparent)]
+    Serenity(Box<serenity::Error>),
+}
+
+pub type Result<T, E = DistoreError> = std::result::Result<T, E>;
+
+impl DistoreError {
+    /// HTTP status of the response, if Discord refused the request.
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Self::Discord { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    pub fn is_rate_limited(&self) -> bool {
+        self.status() == Some(429)
+    }
+
+    pub fn is_permission_denied(&self) -> bool {
+        self.status() == Some(403)
+    }
+}
+
+impl From<serenity::Error> for DistoreError {
+    fn from(e: serenity::Error) -> Self {
+        match e {
+            serenity::Error::Http(HttpError::UnsuccessfulRequest(response)) => Self::Discord {
+                status: response.status_code.as_u16(),
+                code: response.error.code,
+                message: response.error.message,
+            },
+            serenity::Error::Http(HttpError::Request(e)) => Self::Network(Box::new(e)),
+            serenity::Error::Io(e) => Self::Io(e),
+            e => Self::Serenity(Box::new(e)),
+        }
+    }
+}
+
+impl From<ErasureError> for DistoreError {
+    fn from(e: ErasureError) -> Self {
+        Self::Integrity(e.to_string())
+    }
+}
+
+impl From<reqwest::Error> for DistoreError {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Network(Box::new(e))
+    }
+}
@@ -0,0 +1,143 @@
+//! A stand-in for the Discord API, for the tests. It listens on a local port
+//! and answers every request with what a closure returns for it.
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use serde_json::{json, Value};
+
+/// How long a connection may stay silent before it's dropped, so that a test
+/// fails instead of hanging when a request doesn't go as expected.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A request sent to the fake API.
+pub(crate) struct Request {
+    /// Base URL of the fake API, for URLs that point back to it
+    pub base: String,
+    pub method: String,
+    pub path: String,
+    pub body: Vec<u8>,
+}
+
+/// Starts answering requests with `respond`, which returns the body of the
+/// response, usually JSON. Returns the base URL to use as `api_base`.
+/// Every connection is served by a thread of its own, as the bots and the
+/// attachment downloads keep theirs open, but requests are answered one at a
+/// time.
+pub(crate) fn start<F>(respond: F) -> io::Result<String>
+where
+    F: FnMut(&Request) -> String + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let base = format!("http://{}", listener.local_addr()?);
+    let base_ = base.clone();
+    let respond = Arc::new(Mutex::new(respond));
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let (base, respond) = (base_.clone(), respond.clone());
+            // A broken connection only fails the request it carried
+            thread::spawn(move || _serve(stream, &base, &respond));
+        }
+    });
+    Ok(base)
+}
+
+/// A message as Discord returns it, sent by the bot to channel 10.
+pub(crate) fn message(id: u64, content: &str, attachments: &[Value]) -> Value {
+    json!({
+        "id": id.to_string(), "channel_id": "10", "content": content,
+        "attachments": attachments,
+        "author": {"id": "5", "username": "bot", "discriminator": "0", "avatar": null,
+            "bot": true},
+        "timestamp": "2024-01-01T00:00:00.000000+00:00", "edited_timestamp": null,
+        "tts": false, "mention_everyone": false, "mentions": [], "mention_roles": [],
+        "embeds": [], "pinned": false, "type": 0,
+    })
+}
+
+/// Attachments as Discord returns them for the files of a multipart upload,
+/// each `size` bytes long.
+pub(crate) fn attachments(body: &[u8], size: u64) -> Vec<Value> {
+    String::from_utf8_lossy(body)
+        .split("filename=\"")
+        .skip(1)
+        .filter_map(|rest| rest.split_once('"'))
+        .enumerate()
+        .map(|(i, (name, _))| {
+            let url = format!("https://cdn.discordapp.com/attachments/10/{i}/{name}");
+            json!({"id": (i + 1).to_string(), "filename": name, "size": size,
+                "url": url, "proxy_url": url})
+        })
+        .collect()
+}
+
+/// Answers the requests sent over `stream` until the client closes it.
+fn _serve<F: FnMut(&Request) -> String>(
+    stream: TcpStream,
+    base: &str,
+    respond: &Mutex<F>,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+
+    while let Some(request) = _read_request(&mut reader, base)? {
+        // A test that panicked in `respond` fails anyway
+        let body = match respond.lock() {
+            Ok(mut respond) => respond(&request),
+            Err(_) => return Ok(()),
+        };
+        write!(
+            reader.get_mut(),
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )?;
+    }
+    Ok(())
+}
+
+/// Reads the next request, or `None` once the client closed the connection.
+fn _read_request(reader: &mut impl BufRead, base: &str) -> io::Result<Option<Request>> {
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut words = line.split_whitespace();
+    let (Some(method), Some(path)) = (words.next(), words.next()) else {
+        return Err(invalid("request line"));
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+
+    let mut length = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid("headers"));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((key, val)) = header.split_once(':') {
+            if key.eq_ignore_ascii_case("content-length") {
+                length = val.trim().parse().map_err(|_| invalid("content length"))?;
+            }
+        }
+    }
+
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(Request {
+        base: base.into(),
+        method,
+        path,
+        body,
+    }))
+}
@@ -8,30 +8,71 @@ use std::sync::mpsc::TryRecvError;
 use std::sync::{mpsc, Arc, Mutex};
 use std::time::Duration;
 
-use anyhow::anyhow;
 use gtk::gio::{Cancellable, FileQueryInfoFlags, FILE_ATTRIBUTE_STANDARD_NAME};
-use gtk::{glib, Entry, FileDialog, ScrolledWindow};
+use gtk::{glib, DropDown, Entry, FileDialog, ScrolledWindow, Window};
 use gtk::{prelude::*, Align, ApplicationWindow, Box, Label, ListBox, ListBoxRow, Orientation};
 use gtk::{AlertDialog, Application, Button, ProgressBar};
 use indicatif::HumanBytes;
-use serenity::all::{ChannelId, Http};
 
-use crate::commands::{self, delete_internal, download_internal, upload_internal};
-use crate::config::ConfigValue;
+use crate::commands::{self, delete_internal, download_internal, rename_internal, upload_internal};
+use crate::config::{config_path, ConfigValue};
+use crate::error::DistoreError;
 use crate::parser::FileEntry;
+use crate::pool::HttpPool;
 
 const APP_ID: &str = "org.distore.Distore";
 
-pub fn run() {
+/// Runs the GUI with the config in `dir`, or in the user's config directory if
+/// it isn't given.
+pub fn run(dir: Option<PathBuf>) {
+    crate::secret::disable_prompt();
     let app = Application::builder().application_id(APP_ID).build();
 
-    app.connect_activate(build_ui);
+    app.connect_activate(move |app| build_remote_ui(app, None, dir.clone()));
 
     let exitcode = app.run();
     exit(exitcode.into());
 }
 
-fn build_ui(app: &Application) {
+/// Saves the token and channel typed in the settings. This can't ask for the
+/// passphrase of an encrypted config, so it fails if the config is locked.
+fn save_config(
+    token: &str,
+    channel: &str,
+    remote: Option<String>,
+    dir: Option<PathBuf>,
+) -> Result<(), DistoreError> {
+    commands::config(
+        true,
+        "token".into(),
+        token.into(),
+        false,
+        remote.clone(),
+        dir.clone(),
+    )?;
+    commands::config(true, "channel".into(), channel.into(), false, remote, dir)
+}
+
+/// Creates a pool for the tokens in `token`, with the settings of `remote`.
+fn http_pool(
+    token: &ConfigValue,
+    remote: Option<&str>,
+    dir: Option<PathBuf>,
+) -> Result<HttpPool, DistoreError> {
+    let settings = ConfigValue::get_current_settings(&config_path(dir)?, remote)?;
+    Ok(HttpPool::with_settings(&token.tokens(), &settings)?)
+}
+
+fn show_config_error(window: &ApplicationWindow, e: DistoreError) {
+    AlertDialog::builder()
+        .message("Couldn't save the settings")
+        .detail(e.to_string())
+        .build()
+        .show(Some(window));
+}
+
+/// Builds the window for `remote`, or for the global config if it is `None`.
+fn build_remote_ui(app: &Application, remote: Option<String>, dir: Option<PathBuf>) {
     let window = Rc::new(ApplicationWindow::new(app));
     window.set_title(Some("Distore"));
     window.set_default_size(300, 200);
@@ -60,12 +101,12 @@ fn build_ui(app: &Application) {
     container.set_margin_start(margin);
     container.set_margin_end(margin);
 
-    let (token, channel) = commands::get_config_internal(true, None).unwrap_or((
-        ConfigValue::Token("---------------------------".to_string()),
-        ConfigValue::Channel("0000000000001".to_string()),
-    ));
+    let (token, channel) = commands::get_config_internal(true, remote.clone(), dir.clone())
+        .unwrap_or((
+            ConfigValue::Token("---------------------------".to_string()),
+            ConfigValue::Channel("0000000000001".to_string()),
+        ));
     let (token, channel) = (Rc::new(RefCell::new(token)), Rc::new(RefCell::new(channel)));
-    let http = Arc::new(Http::new(token.borrow().inner()));
 
     let top_settings_panel = Rc::new(Box::new(Orientation::Vertical, 0));
 
@@ -82,33 +123,17 @@ fn build_ui(app: &Application) {
     token_box.append(&*token_entry);
 
     let channel_box = Rc::new(Box::new(Orientation::Horizontal, 10));
-    channel_box.append(&Label::new(Some("Channel ID")));
+    channel_box.append(&Label::new(Some("Channel IDs")));
     let channel_entry = Rc::new(Entry::new());
     channel_entry.connect_changed(|entry| {
-        let e = entry.text().parse::<u64>();
-
-        if let Err(e) = e {
-            match e.kind() {
-                std::num::IntErrorKind::PosOverflow => {
-                    entry.set_text(&format!("{}", u64::MAX));
-                }
-                std::num::IntErrorKind::NegOverflow => {
-                    entry.set_text(&format!("{}", u64::MIN));
-                }
-                std::num::IntErrorKind::InvalidDigit => {
-                    let t: Vec<char> = entry.text().chars().collect();
-                    let mut new = t.clone();
-
-                    for (i, x) in t.iter().enumerate() {
-                        if let Err(_) = x.to_string().parse::<u64>() {
-                            new.remove(i);
-                        }
-                    }
-
-                    entry.set_text(&new.into_iter().collect::<String>());
-                }
-                _ => {}
-            }
+        let text = entry.text();
+        let filtered: String = text
+            .chars()
+            .filter(|c| c.is_ascii_digit() || *c == ',')
+            .collect();
+
+        if filtered != text.as_str() {
+            entry.set_text(&filtered);
         }
     });
     channel_box.append(&*channel_entry);
@@ -116,15 +141,45 @@ fn build_ui(app: &Application) {
     token_entry.set_text(token.borrow().inner());
     channel_entry.set_text(channel.borrow().inner());
 
+    let remote_box = Box::new(Orientation::Horizontal, 10);
+    remote_box.append(&Label::new(Some("Remote")));
+    let remotes = commands::get_remotes_internal(dir.clone()).unwrap_or_default();
+    let mut names = vec!["Default"];
+    names.extend(remotes.iter().map(|r| r.as_str()));
+    let remote_dropdown = DropDown::from_strings(&names);
+    remote_dropdown.set_hexpand(true);
+    if let Some(i) = remote
+        .as_ref()
+        .and_then(|r| remotes.iter().position(|n| n == r))
+    {
+        remote_dropdown.set_selected(i as u32 + 1);
+    }
+    let app_ = app.clone();
+    let window_ = window.clone();
+    let dir_ = dir.clone();
+    remote_dropdown.connect_selected_notify(move |dropdown| {
+        let remote = match dropdown.selected() {
+            0 => None,
+            i => remotes.get(i as usize - 1).cloned(),
+        };
+        window_.destroy();
+        build_remote_ui(&app_, remote, dir_.clone());
+    });
+    remote_box.append(&remote_dropdown);
+
+    settings_panel.append(&remote_box);
     settings_panel.append(&*token_box);
     settings_panel.append(&*channel_box);
 
     top_settings_panel.append(&*settings_panel);
 
-    let components = match async_std::task::block_on(commands::list_internal(
-        channel.borrow().inner().parse().unwrap(),
-        &http,
-    )) {
+    let res = async_std::task::block_on(async {
+        let http = Arc::new(http_pool(&token.borrow(), remote.as_deref(), dir.clone())?);
+        let channel = channel.borrow().channels()?[0];
+        let components = commands::list_internal(channel, &http).await?;
+        Ok::<_, DistoreError>((http, components))
+    });
+    let (http, components) = match res {
         Ok(v) => v,
         Err(e) => {
             window.set_child(Some(&*top_settings_panel));
@@ -139,22 +194,24 @@ fn build_ui(app: &Application) {
             let channel_ = channel.clone();
             let app = app.clone();
             let window_ = window.clone();
+            let remote_ = remote.clone();
+            let dir_ = dir.clone();
             retry_btn.connect_clicked(move |_| {
                 token_.replace(ConfigValue::Token(token_entry_.text().to_string()));
                 channel_.replace(ConfigValue::Channel(channel_entry_.text().to_string()));
-                commands::config(true, "token".into(), token_entry_.text().to_string(), None)
-                    .unwrap();
-                commands::config(
-                    true,
-                    "channel".into(),
-                    channel_entry_.text().to_string(),
-                    None,
-                )
-                .unwrap();
+                if let Err(e) = save_config(
+                    &token_entry_.text(),
+                    &channel_entry_.text(),
+                    remote_.clone(),
+                    dir_.clone(),
+                ) {
+                    show_config_error(&window_, e);
+                    return;
+                }
 
                 window_.destroy();
 
-                build_ui(&app);
+                build_remote_ui(&app, remote_.clone(), dir_.clone());
             });
 
             let mut alert_msg = "Couldn't fetch messages";
@@ -211,10 +268,12 @@ fn build_ui(app: &Application) {
     let download_btn = Button::builder().label("Download").build();
     let upload_btn = Button::builder().label("Upload").build();
     let delete_btn = Button::builder().label("Delete").build();
+    let rename_btn = Button::builder().label("Rename").build();
     let settings_btn = Button::builder().label("Settings").build();
     button_box.append(&download_btn);
     button_box.append(&upload_btn);
     button_box.append(&delete_btn);
+    button_box.append(&rename_btn);
     button_box.append(&settings_btn);
 
     let settings_buttons = Rc::new(Box::new(Orientation::Horizontal, 10));
@@ -229,17 +288,20 @@ fn build_ui(app: &Application) {
     let channel_entry_ = channel_entry.clone();
     let token_ = token.clone();
     let channel_ = channel.clone();
+    let remote_ = remote.clone();
+    let dir_ = dir.clone();
+    let window_ = window.clone();
     apply_button.connect_clicked(move |_| {
         token_.replace(ConfigValue::Token(token_entry_.text().to_string()));
         channel_.replace(ConfigValue::Channel(channel_entry_.text().to_string()));
-        commands::config(true, "token".into(), token_entry_.text().to_string(), None).unwrap();
-        commands::config(
-            true,
-            "channel".into(),
-            channel_entry_.text().to_string(),
-            None,
-        )
-        .unwrap();
+        if let Err(e) = save_config(
+            &token_entry_.text(),
+            &channel_entry_.text(),
+            remote_.clone(),
+            dir_.clone(),
+        ) {
+            show_config_error(&window_, e);
+        }
     });
 
     let window_clone = window.clone();
@@ -268,6 +330,8 @@ fn build_ui(app: &Application) {
     let window_clone = window.clone();
     let channel_ = channel.clone();
     let token_ = token.clone();
+    let remote_ = remote.clone();
+    let dir_ = dir.clone();
     delete_btn.connect_clicked(move |_| {
         if let Some(selected_row) = list_box_clone.selected_row() {
             if let Some(box_) = selected_row.child().and_then(|w| w.downcast::<Box>().ok()) {
@@ -309,6 +373,8 @@ fn build_ui(app: &Application) {
                 let window_clone_ = window_clone.clone();
                 let channel_ = channel_.clone();
                 let token_ = token_.clone();
+                let remote_ = remote_.clone();
+                let dir_ = dir_.clone();
                 AlertDialog::builder()
                     .message("Are You Sure?")
                     .detail(format!("Do you really want to delete {}?", name.label()))
@@ -340,14 +406,20 @@ fn build_ui(app: &Application) {
 
                             let (sender, receiver) = mpsc::channel();
 
-                            let http = Http::new(token_.borrow().inner());
-                            let channel_ = channel_.borrow().inner().to_owned();
+                            let http =
+                                http_pool(&token_.borrow(), remote_.as_deref(), dir_.clone());
+                            let channels = channel_.borrow().channels();
                             tokio::spawn(async move {
-                                let res =
-                                    delete_internal(&http, id, channel_.parse().unwrap(), || {
-                                        sender.send((Some(()), None)).unwrap();
-                                    })
-                                    .await;
+                                let res = match (http, channels) {
+                                    (Ok(http), Ok(channels)) => {
+                                        delete_internal(&http, id, channels[0], || {
+                                            sender.send((Some(()), None)).unwrap();
+                                        })
+                                        .await
+                                    }
+                                    (Err(e), _) => Err(e),
+                                    (_, Err(e)) => Err(DistoreError::from(e)),
+                                };
 
                                 sender.send((None, Some(res))).unwrap();
                             });
@@ -403,12 +475,121 @@ fn build_ui(app: &Application) {
         }
     });
 
+    let list_box_clone = list_box.clone();
+    let window_clone = window.clone();
+    let channel_ = channel.clone();
+    let token_ = token.clone();
+    let remote_ = remote.clone();
+    let dir_ = dir.clone();
+    rename_btn.connect_clicked(move |_| {
+        if let Some(selected_row) = list_box_clone.selected_row() {
+            if let Some(box_) = selected_row.child().and_then(|w| w.downcast::<Box>().ok()) {
+                let name_label: Label = box_.first_child().unwrap().downcast().unwrap();
+                let id = box_
+                    .last_child()
+                    .and_then(|w| w.downcast::<Label>().ok())
+                    .unwrap()
+                    .label()
+                    .replace("ID: ", "")
+                    .parse::<u64>()
+                    .unwrap();
+
+                let dialog = Rc::new(
+                    Window::builder()
+                        .title("Rename")
+                        .modal(true)
+                        .transient_for(&*window_clone)
+                        .default_width(300)
+                        .build(),
+                );
+
+                let dialog_box = Box::new(Orientation::Vertical, 10);
+                dialog_box.set_margin_top(20);
+                dialog_box.set_margin_bottom(20);
+                dialog_box.set_margin_start(20);
+                dialog_box.set_margin_end(20);
+
+                let name_entry = Entry::new();
+                name_entry.set_text(&name_label.label());
+                dialog_box.append(&name_entry);
+
+                let dialog_buttons = Box::new(Orientation::Horizontal, 10);
+                dialog_buttons.set_halign(Align::End);
+                let confirm_btn = Button::builder().label("Rename").build();
+                let cancel_btn = Button::builder().label("Cancel").build();
+                dialog_buttons.append(&confirm_btn);
+                dialog_buttons.append(&cancel_btn);
+                dialog_box.append(&dialog_buttons);
+
+                dialog.set_child(Some(&dialog_box));
+
+                let dialog_ = dialog.clone();
+                cancel_btn.connect_clicked(move |_| dialog_.destroy());
+
+                let dialog_ = dialog.clone();
+                let window_clone_ = window_clone.clone();
+                let channel_ = channel_.clone();
+                let token_ = token_.clone();
+                let remote_ = remote_.clone();
+                let dir_ = dir_.clone();
+                confirm_btn.connect_clicked(move |_| {
+                    let new_name = name_entry.text().to_string();
+                    dialog_.destroy();
+
+                    let (sender, receiver) = mpsc::channel();
+
+                    let http = http_pool(&token_.borrow(), remote_.as_deref(), dir_.clone());
+                    let channels = channel_.borrow().channels();
+                    let name = new_name.clone();
+                    tokio::spawn(async move {
+                        let res = match (http, channels) {
+                            (Ok(http), Ok(channels)) => {
+                                rename_internal(&http, id, channels[0], &name).await
+                            }
+                            (Err(e), _) => Err(e),
+                            (_, Err(e)) => Err(DistoreError::from(e)),
+                        };
+
+                        sender.send(res).unwrap();
+                    });
+
+                    let name_label = name_label.clone();
+                    let window_clone = window_clone_.clone();
+                    glib::timeout_add_local(Duration::from_millis(100), move || {
+                        match receiver.try_recv() {
+                            Ok(Ok(_)) => {
+                                name_label.set_label(&new_name);
+                                glib::ControlFlow::Break
+                            }
+                            Ok(Err(e)) => {
+                                AlertDialog::builder()
+                                    .message("Rename Failed")
+                                    .detail(format!("An error occured: {}", e))
+                                    .build()
+                                    .show(Some(&*window_clone));
+                                glib::ControlFlow::Break
+                            }
+                            Err(TryRecvError::Empty) => glib::ControlFlow::Continue,
+                            Err(TryRecvError::Disconnected) => glib::ControlFlow::Break,
+                        }
+                    });
+                });
+
+                dialog.present();
+            }
+        }
+    });
+
     let window_clone = window.clone();
     let channel_ = channel.clone();
     let progress_box_ = progress_box.clone();
     let list_box_ = list_box.clone();
+    let remote_ = remote.clone();
+    let dir_ = dir.clone();
     upload_btn.connect_clicked(move |_| {
         let channel_clone = channel_.clone();
+        let remote_ = remote_.clone();
+        let dir_ = dir_.clone();
         let window_clone_ = window_clone.clone();
         let http_clone = http.clone();
         let progress_box_clone = progress_box_.clone();
@@ -466,24 +647,33 @@ fn build_ui(app: &Application) {
                     let http_ = http_clone.clone();
                     let file_ = file.clone();
                     let id_ = id.clone();
-                    let channel_ = channel_clone.borrow().inner().to_owned();
+                    let channels = channel_clone.borrow().channels();
+                    let options = config_path(dir_.clone())
+                        .map_err(DistoreError::from)
+                        .and_then(|p| {
+                            commands::get_upload_options_internal(&p, remote_.as_deref())
+                        });
                     tokio::spawn(async move {
-                        let res =
-                            upload_internal(&http_, path, channel_.parse().unwrap(), |s, f| {
-                                sender.send((Some((s, f)), None)).unwrap();
-                            })
-                            .await;
-
+                        let res = match (channels, options) {
+                            (Ok(channels), Ok(options)) => {
+                                upload_internal(&http_, path, &channels, &options, |s, f| {
+                                    sender.send((Some((s, f)), None)).unwrap();
+                                })
+                                .await
+                            }
+                            (Err(e), _) => Err(DistoreError::from(e)),
+                            (_, Err(e)) => Err(e),
+                        };
+
+                        // The head was edited to hold the manifest
+                        let res = res.and_then(|v| {
+                            let head = v.first().ok_or(DistoreError::NothingUploaded)?;
+                            Ok((head.id, FileEntry::from_str(&head.content)?))
+                        });
                         match res {
-                            Ok(v) => {
-                                let content = ChannelId::new(channel_.parse().unwrap())
-                                    .message(&http_, v[0].id)
-                                    .await
-                                    .unwrap()
-                                    .content;
-                                let mut f_lock = file_.lock().unwrap();
-                                *f_lock = FileEntry::from_str(&content).unwrap();
-                                id_.store(v[0].id.into(), Ordering::SeqCst);
+                            Ok((id, entry)) => {
+                                *file_.lock().unwrap() = entry;
+                                id_.store(id.into(), Ordering::SeqCst);
                             }
                             Err(e) => sender.send((None, Some(e))).unwrap(),
                         }
@@ -574,6 +764,8 @@ fn build_ui(app: &Application) {
     let window_clone = window.clone();
     let channel_ = channel.clone();
     let token_ = token.clone();
+    let remote_ = remote.clone();
+    let dir_ = dir.clone();
     download_btn.connect_clicked(move |_| {
         if let Some(selected_row) = list_box_clone.selected_row() {
             if let Some(box_) = selected_row.child().and_then(|w| w.downcast::<Box>().ok()) {
@@ -624,28 +816,29 @@ fn build_ui(app: &Application) {
 
                 let path = Arc::new(Mutex::new(PathBuf::new()));
 
-                let channel = channel_.borrow().inner().to_owned();
-                let token = token_.borrow().inner().to_owned();
+                let channels = channel_.borrow().channels();
+                let http = http_pool(&token_.borrow(), remote_.as_deref(), dir_.clone());
                 let window_clone_ = window_clone.clone();
                 let p = path.clone();
 
                 let (sender, receiver) = mpsc::channel();
                 tokio::task::spawn(async move {
                     let sender_ = sender.clone();
-                    let channel = match channel.parse() {
-                        Ok(v) => v,
-                        Err(_) => {
-                            sender
-                                .send((None, Some(anyhow!("Invalid Channel ID"))))
-                                .unwrap();
+                    let (http, channel) = match (http, channels) {
+                        (Ok(http), Ok(v)) => (http, v[0]),
+                        (Err(e), _) => {
+                            sender.send((None, Some(e))).unwrap();
+                            return;
+                        }
+                        (_, Err(e)) => {
+                            sender.send((None, Some(DistoreError::from(e)))).unwrap();
                             return;
                         }
                     };
-                    let result =
-                        download_internal(&Http::new(&token), id, channel, None, move |fraction| {
-                            sender_.send((Some(fraction), None)).unwrap();
-                        })
-                        .await;
+                    let result = download_internal(&http, id, channel, None, move |fraction| {
+                        sender_.send((Some(fraction), None)).unwrap();
+                    })
+                    .await;
 
                     match result {
                         Ok(r) => {
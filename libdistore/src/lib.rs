@@ -1,4 +1,14 @@
+pub mod client;
 pub mod commands;
 pub mod config;
+mod erasure;
+pub mod error;
+#[cfg(test)]
+mod fake_discord;
 pub mod gui;
 pub mod parser;
+pub mod pool;
+mod secret;
+mod server;
+#[cfg(test)]
+mod testing;
@@ -1,12 +1,74 @@
-use std::num::ParseIntError;
+use std::{fmt::Display, num::ParseIntError};
+
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
 use thiserror::Error;
 
-#[derive(Clone, Default)]
+/// First line of every head message written by Distore.
+pub const MANIFEST_HEADER: &str =
+    "### This message is generated by Distore. Do not edit this message.";
+
+/// Manifest version written by [`FileEntry`]'s `Display` implementation.
+/// Messages without a `v` key are parsed as version 1.
+pub const MANIFEST_VERSION: u32 = 2;
+
+/// Characters escaped in manifest values. Newlines would allow injecting keys.
+const VALUE_SET: &AsciiSet = &CONTROLS.add(b'%').add(b'=');
+
+#[derive(Clone, Default, Debug, PartialEq)]
 pub struct FileEntry {
+    /// Name of the file, as written in the head message. Anyone who can post
+    /// in the channel can write one, so it may hold path separators or `..`
+    /// and must be reduced to its last component before it's used as a path
     pub name: Option<String>,
     pub size: Option<u64>,
     pub len: Option<usize>,
     pub next: Option<u64>,
+    /// Channel of the next message, when it differs from this one's
+    pub next_channel: Option<u64>,
+    /// Size of every part except the last one
+    pub part_size: Option<u64>,
+    /// Hex encoded SHA-256 of the whole file
+    pub sha256: Option<String>,
+    /// How the file was compressed before it was split, like `deflate`. The
+    /// size, part size and hashes are those of the compressed file then
+    pub compression: Option<String>,
+    /// Size of a compressed file before it was compressed
+    pub original_size: Option<u64>,
+    /// Every message of the entry in order, starting with the head message.
+    /// Empty for uploads made before the index was introduced.
+    pub parts: Vec<PartRef>,
+    /// Message in the head message's channel with the part index attached,
+    /// when the index doesn't fit in the manifest
+    pub index: Option<u64>,
+    /// Head messages of the other copies of the entry
+    pub replicas: Vec<MessageRef>,
+    /// Parity parts computed for every group of data parts
+    pub parity: Option<usize>,
+    /// Number of data parts in each parity group
+    pub parity_group: Option<usize>,
+    /// Messages holding the parity parts, in order
+    pub parity_messages: Vec<MessageRef>,
+    /// Truncated SHA-256 of every part attached to this message
+    pub hashes: Vec<String>,
+}
+
+/// A message holding some of the parts of an entry.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PartRef {
+    /// Message ID
+    pub id: u64,
+    /// Byte offset of the first part attached to the message
+    pub offset: u64,
+    /// Channel of the message, when it differs from the head message's
+    pub channel: Option<u64>,
+}
+
+/// A message in any channel, such as the head message of a copy of an entry.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MessageRef {
+    /// Message ID
+    pub id: u64,
+    pub channel: u64,
 }
 
 #[derive(Error, Debug)]
@@ -14,8 +76,27 @@ pub enum ParseError {
     #[error("Invalid input: {0}")]
     InvalidInput(String),
 
-    #[error(transparent)]
-    ParseIntError(#[from] ParseIntError),
+    #[error("Line {0} is missing a '=' separator")]
+    MissingSeparator(usize),
+
+    #[error("Unsupported manifest version: {0}")]
+    UnsupportedVersion(String),
+
+    #[error("The version must be the first key of the manifest")]
+    MisplacedVersion,
+
+    #[error("Duplicate key: {0}")]
+    DuplicateKey(String),
+
+    #[error("Invalid encoding for key '{0}'")]
+    InvalidEncoding(String),
+
+    #[error("Invalid number for key '{key}': {source}")]
+    InvalidNumber {
+        key: String,
+        #[source]
+        source: ParseIntError,
+    },
 }
 
 impl std::str::FromStr for FileEntry {
@@ -23,30 +104,334 @@ impl std::str::FromStr for FileEntry {
 
     fn from_str(str: &str) -> Result<FileEntry, Self::Err> {
         let mut out = FileEntry::default();
-        if str.is_empty() {
-            return Ok(out);
-        }
-        for line in str.split("\n") {
-            if line.starts_with("#") {
+        let mut version = 1;
+        let mut seen: Vec<&str> = Vec::new();
+
+        for (i, line) in str.lines().enumerate() {
+            if line.starts_with("#") || line.trim().is_empty() {
                 continue;
             }
 
-            let mut assignment = line.split("=");
-            let key = assignment
-                .next()
-                .ok_or(ParseError::InvalidInput(str.into()))?;
-            let val = assignment
-                .next()
-                .ok_or(ParseError::InvalidInput(str.into()))?;
+            let (key, val) = line
+                .split_once("=")
+                .ok_or(ParseError::MissingSeparator(i + 1))?;
+
+            if seen.contains(&key) {
+                return Err(ParseError::DuplicateKey(key.into()));
+            }
+
+            if key == "v" {
+                if !seen.is_empty() {
+                    return Err(ParseError::MisplacedVersion);
+                }
+                version = match val.parse::<u32>() {
+                    Ok(v) if (2..=MANIFEST_VERSION).contains(&v) => v,
+                    _ => return Err(ParseError::UnsupportedVersion(val.into())),
+                };
+            }
+            seen.push(key);
+
+            let val = match version {
+                1 => val.to_string(),
+                _ => percent_decode_str(val)
+                    .decode_utf8()
+                    .map_err(|_| ParseError::InvalidEncoding(key.into()))?
+                    .into_owned(),
+            };
 
             match key {
-                "name" => out.name = Some(val.into()),
-                "size" => out.size = Some(val.parse()?),
-                "len" => out.len = Some(val.parse()?),
-                "next" => out.next = Some(val.parse()?),
+                "name" => out.name = Some(val),
+                "size" => out.size = Some(parse_number(key, &val)?),
+                "len" => out.len = Some(parse_number(key, &val)?),
+                "next" => out.next = Some(parse_number(key, &val)?),
+                "next_channel" => out.next_channel = Some(parse_number(key, &val)?),
+                "psize" => out.part_size = Some(parse_number(key, &val)?),
+                "sha256" => out.sha256 = Some(val),
+                "compression" => out.compression = Some(val),
+                "osize" => out.original_size = Some(parse_number(key, &val)?),
+                "parts" => out.parts = parse_parts(key, &val)?,
+                "index" => out.index = Some(parse_number(key, &val)?),
+                "replicas" => out.replicas = parse_message_refs(key, &val)?,
+                "parity" => out.parity = Some(parse_number(key, &val)?),
+                "pgroup" => out.parity_group = Some(parse_number(key, &val)?),
+                "pmsgs" => out.parity_messages = parse_message_refs(key, &val)?,
+                "hashes" => out.hashes = val.split(",").map(String::from).collect(),
                 _ => {}
             }
         }
         Ok(out)
     }
 }
+
+impl Display for FileEntry {
+    /// Serializes the entry as a versioned manifest. The header is only
+    /// written for head messages, which are the ones carrying a name.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.name.is_some() {
+            writeln!(f, "{MANIFEST_HEADER}")?;
+        }
+        write!(f, "v={MANIFEST_VERSION}")?;
+
+        if let Some(name) = &self.name {
+            write!(f, "\nname={}", utf8_percent_encode(name, VALUE_SET))?;
+        }
+        if let Some(size) = self.size {
+            write!(f, "\nsize={size}")?;
+        }
+        if let Some(len) = self.len {
+            write!(f, "\nlen={len}")?;
+        }
+        if let Some(next) = self.next {
+            write!(f, "\nnext={next}")?;
+        }
+        if let Some(next_channel) = self.next_channel {
+            write!(f, "\nnext_channel={next_channel}")?;
+        }
+        if let Some(part_size) = self.part_size {
+            write!(f, "\npsize={part_size}")?;
+        }
+        if let Some(sha256) = &self.sha256 {
+            write!(f, "\nsha256={}", utf8_percent_encode(sha256, VALUE_SET))?;
+        }
+        if let Some(compression) = &self.compression {
+            write!(
+                f,
+                "\ncompression={}",
+                utf8_percent_encode(compression, VALUE_SET)
+            )?;
+        }
+        if let Some(original_size) = self.original_size {
+            write!(f, "\nosize={original_size}")?;
+        }
+        if !self.parts.is_empty() {
+            let parts: Vec<String> = self.parts.iter().map(PartRef::to_string).collect();
+            write!(f, "\nparts={}", parts.join(","))?;
+        }
+        if let Some(index) = self.index {
+            write!(f, "\nindex={index}")?;
+        }
+        if !self.replicas.is_empty() {
+            let replicas: Vec<String> = self.replicas.iter().map(MessageRef::to_string).collect();
+            write!(f, "\nreplicas={}", replicas.join(","))?;
+        }
+        if let Some(parity) = self.parity {
+            write!(f, "\nparity={parity}")?;
+        }
+        if let Some(parity_group) = self.parity_group {
+            write!(f, "\npgroup={parity_group}")?;
+        }
+        if !self.parity_messages.is_empty() {
+            let messages: Vec<String> = self
+                .parity_messages
+                .iter()
+                .map(MessageRef::to_string)
+                .collect();
+            write!(f, "\npmsgs={}", messages.join(","))?;
+        }
+        if !self.hashes.is_empty() {
+            write!(f, "\nhashes={}", self.hashes.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for PartRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.id, self.offset)?;
+        if let Some(channel) = self.channel {
+            write!(f, ":{channel}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for MessageRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.id, self.channel)
+    }
+}
+
+fn parse_message_refs(key: &str, val: &str) -> Result<Vec<MessageRef>, ParseError> {
+    val.split(",")
+        .map(|message| {
+            let (id, channel) = message
+                .split_once(":")
+                .ok_or(ParseError::InvalidInput(message.into()))?;
+            Ok(MessageRef {
+                id: parse_number(key, id)?,
+                channel: parse_number(key, channel)?,
+            })
+        })
+        .collect()
+}
+
+fn parse_parts(key: &str, val: &str) -> Result<Vec<PartRef>, ParseError> {
+    val.split(",")
+        .map(|part| {
+            let mut fields = part.split(":");
+            let (Some(id), Some(offset)) = (fields.next(), fields.next()) else {
+                return Err(ParseError::InvalidInput(part.into()));
+            };
+            let channel = fields.next().map(|c| parse_number(key, c)).transpose()?;
+            if fields.next().is_some() {
+                return Err(ParseError::InvalidInput(part.into()));
+            }
+
+            Ok(PartRef {
+                id: parse_number(key, id)?,
+                offset: parse_number(key, offset)?,
+                channel,
+            })
+        })
+        .collect()
+}
+
+fn parse_number<T: std::str::FromStr<Err = ParseIntError>>(
+    key: &str,
+    val: &str,
+) -> Result<T, ParseError> {
+    val.parse().map_err(|source| ParseError::InvalidNumber {
+        key: key.into(),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn full() -> FileEntry {
+        FileEntry {
+            name: Some("report.pdf".into()),
+            size: Some(52_428_800),
+            len: Some(10),
+            next: Some(1200),
+            next_channel: Some(34),
+            part_size: Some(10_485_760),
+            sha256: Some("ab".repeat(32)),
+            compression: Some("deflate".into()),
+            original_size: Some(104_857_600),
+            parts: vec![
+                PartRef {
+                    id: 1000,
+                    offset: 0,
+                    channel: None,
+                },
+                PartRef {
+                    id: 1200,
+                    offset: 10_485_760,
+                    channel: Some(34),
+                },
+            ],
+            index: Some(1500),
+            replicas: vec![MessageRef {
+                id: 2000,
+                channel: 56,
+            }],
+            parity: Some(2),
+            parity_group: Some(4),
+            parity_messages: vec![MessageRef {
+                id: 3000,
+                channel: 12,
+            }],
+            hashes: vec!["0123456789abcdef".into(), "fedcba9876543210".into()],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let entry = full();
+        let text = entry.to_string();
+        assert!(text.starts_with(MANIFEST_HEADER));
+        assert_eq!(FileEntry::from_str(&text).unwrap(), entry);
+    }
+
+    #[test]
+    fn round_trip_without_name() {
+        let entry = FileEntry {
+            len: Some(10),
+            next: Some(5),
+            ..Default::default()
+        };
+        let text = entry.to_string();
+        assert!(!text.starts_with(MANIFEST_HEADER));
+        assert_eq!(FileEntry::from_str(&text).unwrap(), entry);
+    }
+
+    #[test]
+    fn escapes_names() {
+        for name in ["a=b.txt", "line\nsize=1", "100%.txt", "%41", "naïve ☃.txt"] {
+            let entry = FileEntry {
+                name: Some(name.into()),
+                size: Some(7),
+                ..Default::default()
+            };
+            let text = entry.to_string();
+            assert_eq!(text.lines().count(), 4, "{text}");
+            assert_eq!(FileEntry::from_str(&text).unwrap(), entry);
+        }
+    }
+
+    #[test]
+    fn version_1() {
+        let text = format!("{MANIFEST_HEADER}\nname=100%25 a.txt\nsize=12\nlen=1\nnext=99");
+        let entry = FileEntry::from_str(&text).unwrap();
+        // Version 1 values were written as they are
+        assert_eq!(entry.name.as_deref(), Some("100%25 a.txt"));
+        assert_eq!(entry.size, Some(12));
+        assert_eq!(entry.len, Some(1));
+        assert_eq!(entry.next, Some(99));
+    }
+
+    #[test]
+    fn ignores_unknown_keys() {
+        let entry = FileEntry::from_str("v=2\nname=a\nfuture=1").unwrap();
+        assert_eq!(entry.name.as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(
+            FileEntry::from_str("v=2\nname=a\nsize"),
+            Err(ParseError::MissingSeparator(3))
+        ));
+        assert!(matches!(
+            FileEntry::from_str("v=3\nname=a"),
+            Err(ParseError::UnsupportedVersion(v)) if v == "3"
+        ));
+        assert!(matches!(
+            FileEntry::from_str("v=1\nname=a"),
+            Err(ParseError::UnsupportedVersion(_))
+        ));
+        assert!(matches!(
+            FileEntry::from_str("name=a\nv=2"),
+            Err(ParseError::MisplacedVersion)
+        ));
+        assert!(matches!(
+            FileEntry::from_str("v=2\nname=a\nname=b"),
+            Err(ParseError::DuplicateKey(k)) if k == "name"
+        ));
+        assert!(matches!(
+            FileEntry::from_str("v=2\nname=%FF"),
+            Err(ParseError::InvalidEncoding(k)) if k == "name"
+        ));
+        assert!(matches!(
+            FileEntry::from_str("v=2\nsize=ten"),
+            Err(ParseError::InvalidNumber { key, .. }) if key == "size"
+        ));
+        assert!(matches!(
+            FileEntry::from_str("v=2\nparts=1"),
+            Err(ParseError::InvalidInput(p)) if p == "1"
+        ));
+        assert!(matches!(
+            FileEntry::from_str("v=2\nparts=1:0:2:3"),
+            Err(ParseError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            FileEntry::from_str("v=2\nreplicas=1:x"),
+            Err(ParseError::InvalidNumber { key, .. }) if key == "replicas"
+        ));
+    }
+}
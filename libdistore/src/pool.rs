@@ -0,0 +1,230 @@
+use std::{
+    collections::HashMap,
+    ops::Deref,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::SystemTime,
+};
+
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    Engine,
+};
+use serenity::{
+    all::{Http, Message},
+    http::{HttpBuilder, RatelimitingBucket, Route},
+};
+
+use crate::{
+    commands::CONCURRENCY,
+    config::{ConfigError, Settings},
+};
+
+const TOKEN_ENGINE: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Clients for several bots that can access the same channels. Requests are
+/// spread across them so a transfer isn't held back by a single bot's rate
+/// limits. Dereferences to the first client for requests that don't need it.
+/// A pool always has at least one client.
+pub struct HttpPool {
+    clients: Vec<PoolClient>,
+    concurrency: usize,
+    /// Client for downloading attachments
+    client: reqwest::Client,
+}
+
+struct PoolClient {
+    http: Http,
+    bot_id: Option<u64>,
+    in_flight: AtomicUsize,
+    /// Budget last read for every bucket, for when it can't be read
+    budgets: Mutex<HashMap<RatelimitingBucket, i64>>,
+}
+
+/// A client picked from the pool. Counts as busy until dropped.
+pub struct PoolGuard<'a> {
+    client: &'a PoolClient,
+}
+
+impl HttpPool {
+    /// Creates a client for every token. Fails with [`ConfigError::NoToken`]
+    /// if there are none.
+    pub fn new<S: AsRef<str>>(tokens: &[S]) -> Result<Self, ConfigError> {
+        Self::with_settings(tokens, &Settings::default())
+    }
+
+    /// Like [`HttpPool::new`], but sends the requests to the `api_base` of
+    /// `settings`, through its `proxy`, and transfers `concurrency` parts at
+    /// once if they are set. Rate limits are left to the proxy at `api_base`,
+    /// as serenity doesn't send requests through it otherwise. Fails if a
+    /// setting is out of range, as a concurrency of 0 would never transfer
+    /// anything.
+    pub fn with_settings<S: AsRef<str>>(
+        tokens: &[S],
+        settings: &Settings,
+    ) -> Result<Self, ConfigError> {
+        if tokens.is_empty() {
+            return Err(ConfigError::NoToken);
+        }
+        settings.validate()?;
+
+        let invalid_proxy = |p: &str| ConfigError::InvalidValue("proxy".into(), p.into());
+        let mut client = reqwest::Client::builder();
+        // Serenity is on an older reqwest, and needs a client of its own
+        let mut serenity_client = serenity_reqwest::Client::builder().use_rustls_tls();
+        if let Some(proxy) = &settings.proxy {
+            client = client.proxy(reqwest::Proxy::all(proxy).map_err(|_| invalid_proxy(proxy))?);
+            serenity_client = serenity_client
+                .proxy(serenity_reqwest::Proxy::all(proxy).map_err(|_| invalid_proxy(proxy))?);
+        }
+        let client = client.build().expect("Cannot build reqwest::Client");
+        let serenity_client = serenity_client
+            .build()
+            .expect("Cannot build reqwest::Client");
+
+        let clients = tokens
+            .iter()
+            .map(|token| {
+                let mut builder = HttpBuilder::new(token.as_ref()).client(serenity_client.clone());
+                if let Some(api_base) = &settings.api_base {
+                    builder = builder
+                        .proxy(api_base.trim_end_matches('/'))
+                        .ratelimiter_disabled(true);
+                }
+                PoolClient {
+                    http: builder.build(),
+                    bot_id: bot_id(token.as_ref()),
+                    in_flight: AtomicUsize::new(0),
+                    budgets: Mutex::default(),
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            clients,
+            concurrency: settings.concurrency.unwrap_or(CONCURRENCY),
+            client,
+        })
+    }
+
+    /// Client to download attachments with, going through the configured
+    /// proxy.
+    pub fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    /// Number of parts to transfer at once.
+    pub fn concurrency(&self) -> usize {
+        self.concurrency
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    /// User IDs of the bots in the pool, as far as they can be read from the tokens.
+    pub fn bot_ids(&self) -> Vec<u64> {
+        self.clients.iter().filter_map(|c| c.bot_id).collect()
+    }
+
+    /// Returns the client of the bot that sent `msg`. Only the author can edit
+    /// a message, and deleting someone else's needs extra permissions.
+    pub fn client_for(&self, msg: &Message) -> &Http {
+        self.clients
+            .iter()
+            .find(|c| c.bot_id == Some(msg.author.id.get()))
+            .map(|c| &c.http)
+            .unwrap_or(self)
+    }
+
+    /// Returns the client with the most rate limit budget left for `route`.
+    pub async fn pick(&self, route: Route<'_>) -> PoolGuard<'_> {
+        let bucket = route.ratelimiting_bucket();
+
+        let mut best = &self.clients[0];
+        let mut best_score = i64::MIN;
+        for client in self.clients.iter() {
+            let score =
+                client.budget(bucket).await - client.in_flight.load(Ordering::SeqCst) as i64;
+            if score > best_score {
+                best = client;
+                best_score = score;
+            }
+        }
+
+        best.in_flight.fetch_add(1, Ordering::SeqCst);
+        PoolGuard { client: best }
+    }
+}
+
+impl Deref for HttpPool {
+    type Target = Http;
+
+    fn deref(&self) -> &Self::Target {
+        &self.clients[0].http
+    }
+}
+
+impl PoolClient {
+    /// Number of requests this client can still make in `bucket` before it has
+    /// to wait. Buckets that haven't been used yet are assumed to be full.
+    async fn budget(&self, bucket: RatelimitingBucket) -> i64 {
+        let Some(ratelimiter) = &self.http.ratelimiter else {
+            return i64::MAX / 2;
+        };
+        let routes = ratelimiter.routes();
+        let routes = routes.read().await;
+        let Some(ratelimit) = routes.get(&bucket) else {
+            return i64::MAX / 2;
+        };
+
+        // The ratelimiter holds the lock while a request waits for its turn,
+        // and for a moment around every request. Go by the last budget read
+        // then, as the requests in flight already count against the client.
+        let budget = match ratelimit.try_lock() {
+            Ok(ratelimit) => match ratelimit.reset() {
+                Some(reset) if reset <= SystemTime::now() => ratelimit.limit(),
+                _ => ratelimit.remaining(),
+            },
+            Err(_) => {
+                let budgets = self.budgets.lock().unwrap();
+                return budgets.get(&bucket).copied().unwrap_or(i64::MAX / 2);
+            }
+        };
+        self.budgets.lock().unwrap().insert(bucket, budget);
+        budget
+    }
+}
+
+impl Deref for PoolGuard<'_> {
+    type Target = Http;
+
+    fn deref(&self) -> &Self::Target {
+        &self.client.http
+    }
+}
+
+impl Drop for PoolGuard<'_> {
+    fn drop(&mut self) {
+        self.client.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The first segment of a bot token is the bot's user ID in base64.
+fn bot_id(token: &str) -> Option<u64> {
+    let id = token.trim_start_matches("Bot ").split('.').next()?;
+    String::from_utf8(TOKEN_ENGINE.decode(id).ok()?)
+        .ok()?
+        .parse()
+        .ok()
+}
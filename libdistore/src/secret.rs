@@ -0,0 +1,385 @@
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::{self, IsTerminal, Write},
+    num::NonZeroU32,
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    pbkdf2,
+    rand::{SecureRandom, SystemRandom},
+};
+use thiserror::Error;
+
+use crate::config::ConfigFile;
+
+/// Prefix of the config values that are stored encrypted
+const ENCRYPTED_PREFIX: &str = "enc:";
+/// Section of the INI config of older versions holding the salt of the
+/// passphrase and a value to check it with
+pub(crate) const SECTION: &str = "encryption";
+const CHECK: &str = "distore";
+const ITERATIONS: NonZeroU32 = match NonZeroU32::new(100_000) {
+    Some(n) => n,
+    None => panic!("ITERATIONS must not be zero"),
+};
+const SALT_LEN: usize = 16;
+
+/// Whether the passphrase may be asked for in the terminal
+static PROMPT: AtomicBool = AtomicBool::new(true);
+
+#[derive(Error, Debug)]
+pub enum SecretError {
+    #[error("The config has encrypted values. Run distore in a terminal or set DISTORE_PASSPHRASE to unlock it")]
+    Locked,
+
+    #[error("Wrong passphrase")]
+    WrongPassphrase,
+
+    #[error("Passphrases don't match")]
+    Mismatch,
+
+    #[error("The config has encrypted values but no passphrase was ever set")]
+    NotSetUp,
+
+    #[error("Malformed encrypted value")]
+    Malformed,
+
+    #[error("Failed to set up encryption")]
+    Crypto,
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+type Result<T> = std::result::Result<T, SecretError>;
+
+/// AES-256-GCM key derived from the passphrase.
+pub(crate) struct Key([u8; 32]);
+
+/// Where the key can come from without asking for the passphrase.
+struct Sources {
+    /// File the key is cached in for the session, if the platform has one
+    cache: Option<PathBuf>,
+    /// Passphrase given in `DISTORE_PASSPHRASE`
+    passphrase: Option<String>,
+}
+
+impl Sources {
+    fn from_env() -> Self {
+        Self {
+            cache: _cache_path(),
+            passphrase: env::var("DISTORE_PASSPHRASE").ok(),
+        }
+    }
+}
+
+pub(crate) fn is_encrypted(value: &str) -> bool {
+    value.starts_with(ENCRYPTED_PREFIX)
+}
+
+/// Returns whether a passphrase was set for `conf`. Tokens written to such a
+/// config are always encrypted.
+pub(crate) fn is_set_up(conf: &ConfigFile) -> bool {
+    !conf.encryption.is_empty()
+}
+
+/// Unlocks `conf`, asking for a new passphrase first if none was set.
+pub(crate) fn set_up(conf: &mut ConfigFile) -> Result<Key> {
+    _set_up(conf, &Sources::from_env())
+}
+
+fn _set_up(conf: &mut ConfigFile, sources: &Sources) -> Result<Key> {
+    if is_set_up(conf) {
+        return _unlock(conf, sources);
+    }
+
+    let passphrase = match _given_passphrase(conf, sources)? {
+        Some(p) => p,
+        None => {
+            let passphrase = _prompt("New passphrase")?;
+            if _prompt("Repeat the passphrase")? != passphrase {
+                return Err(SecretError::Mismatch);
+            }
+            passphrase
+        }
+    };
+
+    let mut salt = [0; SALT_LEN];
+    SystemRandom::new()
+        .fill(&mut salt)
+        .map_err(|_| SecretError::Crypto)?;
+    let key = _derive(&passphrase, &salt);
+
+    conf.encryption
+        .insert("salt".into(), BASE64_STANDARD.encode(salt));
+    conf.encryption
+        .insert("check".into(), encrypt(&key, CHECK)?);
+    _cache(sources, &salt, &key)?;
+
+    Ok(key)
+}
+
+/// Returns the key of `conf`. It comes from the key cached for this session,
+/// `DISTORE_PASSPHRASE`, the file at `encryption_key_path` or a passphrase
+/// typed in the terminal, in that order.
+pub(crate) fn unlock(conf: &ConfigFile) -> Result<Key> {
+    _unlock(conf, &Sources::from_env())
+}
+
+fn _unlock(conf: &ConfigFile, sources: &Sources) -> Result<Key> {
+    if !is_set_up(conf) {
+        return Err(SecretError::NotSetUp);
+    }
+    let salt = conf
+        .encryption
+        .get("salt")
+        .and_then(|s| BASE64_STANDARD.decode(s).ok())
+        .ok_or(SecretError::Malformed)?;
+    let check = conf.encryption.get("check").ok_or(SecretError::Malformed)?;
+    let valid = |key: &Key| decrypt(key, check).is_ok_and(|c| c == CHECK);
+
+    if let Some(key) = _cached(sources, &salt).filter(valid) {
+        return Ok(key);
+    }
+
+    let passphrase = match _given_passphrase(conf, sources)? {
+        Some(p) => p,
+        None => _prompt("Passphrase")?,
+    };
+    let key = _derive(&passphrase, &salt);
+    if !valid(&key) {
+        return Err(SecretError::WrongPassphrase);
+    }
+    _cache(sources, &salt, &key)?;
+
+    Ok(key)
+}
+
+/// Stops asking for the passphrase in the terminal, for frontends that can't
+/// answer a prompt there, like the GUI. The key then has to be cached for the
+/// session or come from `DISTORE_PASSPHRASE`.
+pub(crate) fn disable_prompt() {
+    PROMPT.store(false, Ordering::Relaxed);
+}
+
+/// Forgets the key cached for this session.
+pub(crate) fn lock() -> Result<()> {
+    _lock(&Sources::from_env())
+}
+
+fn _lock(sources: &Sources) -> Result<()> {
+    match &sources.cache {
+        Some(path) if path.exists() => Ok(fs::remove_file(path)?),
+        _ => Ok(()),
+    }
+}
+
+pub(crate) fn encrypt(key: &Key, plain: &str) -> Result<String> {
+    let mut nonce = [0; NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| SecretError::Crypto)?;
+
+    let mut data = plain.as_bytes().to_vec();
+    _cipher(key)?
+        .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut data)
+        .map_err(|_| SecretError::Crypto)?;
+
+    let mut out = nonce.to_vec();
+    out.extend(data);
+    Ok(format!("{ENCRYPTED_PREFIX}{}", BASE64_STANDARD.encode(out)))
+}
+
+pub(crate) fn decrypt(key: &Key, value: &str) -> Result<String> {
+    let mut data = value
+        .strip_prefix(ENCRYPTED_PREFIX)
+        .and_then(|v| BASE64_STANDARD.decode(v).ok())
+        .filter(|d| d.len() > NONCE_LEN)
+        .ok_or(SecretError::Malformed)?;
+    let mut sealed = data.split_off(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(&data).map_err(|_| SecretError::Malformed)?;
+
+    let plain = _cipher(key)?
+        .open_in_place(nonce, Aad::empty(), &mut sealed)
+        .map_err(|_| SecretError::WrongPassphrase)?;
+    String::from_utf8(plain.to_vec()).map_err(|_| SecretError::Malformed)
+}
+
+fn _cipher(key: &Key) -> Result<LessSafeKey> {
+    let key = UnboundKey::new(&AES_256_GCM, &key.0).map_err(|_| SecretError::Crypto)?;
+    Ok(LessSafeKey::new(key))
+}
+
+fn _derive(passphrase: &str, salt: &[u8]) -> Key {
+    let mut key = [0; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        ITERATIONS,
+        salt,
+        passphrase.as_bytes(),
+        &mut key,
+    );
+    Key(key)
+}
+
+/// Returns the passphrase from `DISTORE_PASSPHRASE`, or from the file set as
+/// `encryption_key_path` in the global section of `conf`, without its trailing
+/// newline.
+fn _given_passphrase(conf: &ConfigFile, sources: &Sources) -> Result<Option<String>> {
+    if let Some(p) = &sources.passphrase {
+        return Ok(Some(p.clone()));
+    }
+    match &conf.global.encryption_key_path {
+        Some(path) => Ok(Some(
+            fs::read_to_string(path)?
+                .trim_end_matches(['\r', '\n'])
+                .to_string(),
+        )),
+        None => Ok(None),
+    }
+}
+
+fn _prompt(message: &str) -> Result<String> {
+    if !PROMPT.load(Ordering::Relaxed) || !io::stdin().is_terminal() {
+        return Err(SecretError::Locked);
+    }
+    Ok(rpassword::prompt_password(format!("{message}: "))?)
+}
+
+/// The key is cached in the runtime directory, which only the user can read
+/// and which lives in memory until they log out. The key is written there as
+/// is, so anything running as the user can read it in the meantime, like it
+/// could read the passphrase from `DISTORE_PASSPHRASE`. `distore lock` removes
+/// it. Platforms without a runtime directory ask for the passphrase every time.
+fn _cache_path() -> Option<PathBuf> {
+    dirs::runtime_dir().map(|d| d.join("distore-session"))
+}
+
+fn _cached(sources: &Sources, salt: &[u8]) -> Option<Key> {
+    let cached = fs::read_to_string(sources.cache.as_ref()?).ok()?;
+    let (cached_salt, key) = cached.trim().split_once(':')?;
+    if BASE64_STANDARD.decode(cached_salt).ok()? != salt {
+        return None;
+    }
+    BASE64_STANDARD.decode(key).ok()?.try_into().ok().map(Key)
+}
+
+fn _cache(sources: &Sources, salt: &[u8], key: &Key) -> Result<()> {
+    let Some(path) = &sources.cache else {
+        return Ok(());
+    };
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    // The mode only applies to new files
+    #[cfg(unix)]
+    fs::set_permissions(path, std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+
+    write!(
+        file,
+        "{}:{}",
+        BASE64_STANDARD.encode(salt),
+        BASE64_STANDARD.encode(key.0)
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::scratch;
+
+    #[test]
+    fn round_trip() {
+        let key = _derive("hunter2", b"0123456789abcdef");
+        let sealed = encrypt(&key, "NQ.token").unwrap();
+
+        assert!(is_encrypted(&sealed));
+        assert!(!sealed.contains("NQ.token"));
+        assert_ne!(sealed, encrypt(&key, "NQ.token").unwrap());
+        assert_eq!(decrypt(&key, &sealed).unwrap(), "NQ.token");
+    }
+
+    #[test]
+    fn wrong_key() {
+        let sealed = encrypt(&_derive("hunter2", b"0123456789abcdef"), "NQ.token").unwrap();
+        let other = _derive("hunter3", b"0123456789abcdef");
+
+        assert!(matches!(
+            decrypt(&other, &sealed),
+            Err(SecretError::WrongPassphrase)
+        ));
+    }
+
+    #[test]
+    fn malformed() {
+        let key = _derive("hunter2", b"0123456789abcdef");
+
+        for value in ["NQ.token", "enc:not base64", "enc:AAAA"] {
+            assert!(matches!(decrypt(&key, value), Err(SecretError::Malformed)));
+        }
+    }
+
+    #[test]
+    fn unlock_with_passphrase() {
+        let runtime = scratch("secret");
+        let mut sources = Sources {
+            cache: Some(runtime.join("distore-session")),
+            passphrase: Some("hunter2".into()),
+        };
+
+        assert!(matches!(
+            _unlock(&ConfigFile::default(), &sources),
+            Err(SecretError::NotSetUp)
+        ));
+
+        let mut conf = ConfigFile::default();
+        let sealed = encrypt(&_set_up(&mut conf, &sources).unwrap(), "NQ.token").unwrap();
+        assert!(is_set_up(&conf));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(sources.cache.as_ref().unwrap())
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // The key cached by set_up unlocks whatever the passphrase is
+        sources.passphrase = Some("hunter3".into());
+        let unlock = |sources: &Sources| decrypt(&_unlock(&conf, sources)?, &sealed);
+        assert_eq!(unlock(&sources).unwrap(), "NQ.token");
+
+        _lock(&sources).unwrap();
+        assert!(matches!(
+            unlock(&sources),
+            Err(SecretError::WrongPassphrase)
+        ));
+
+        sources.passphrase = Some("hunter2".into());
+        assert_eq!(unlock(&sources).unwrap(), "NQ.token");
+
+        // Without the variable, the passphrase is read from the key file
+        _lock(&sources).unwrap();
+        sources.passphrase = None;
+        let key_file = runtime.join("key");
+        fs::write(&key_file, "hunter2\n").unwrap();
+        let mut conf = conf.clone();
+        conf.global.encryption_key_path = Some(key_file);
+        assert_eq!(
+            decrypt(&_unlock(&conf, &sources).unwrap(), &sealed).unwrap(),
+            "NQ.token"
+        );
+
+        fs::remove_dir_all(runtime).unwrap();
+    }
+}
@@ -0,0 +1,351 @@
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+
+use flate2::write::DeflateDecoder;
+use futures::{future, stream, StreamExt, TryStreamExt};
+use http_body_util::{combinators::BoxBody, BodyExt, Full, StreamBody};
+use hyper::{
+    body::{Bytes, Frame, Incoming},
+    header::{ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, RANGE},
+    http::response,
+    server::conn::http1,
+    service::service_fn,
+    Method, Request, Response, StatusCode,
+};
+use hyper_util::rt::TokioIo;
+use log::{info, warn};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serenity::all::Message;
+use tokio::net::TcpListener;
+
+use crate::{
+    commands::{
+        _get_download_variables, _inflate, _range_slots, _read_slots, list_internal, DEFLATE,
+        PART_SIZE,
+    },
+    error::{DistoreError, Result},
+    parser::FileEntry,
+    pool::HttpPool,
+};
+
+type Body = BoxBody<Bytes, DistoreError>;
+
+/// Characters escaped in the file name of a link. Everything else that isn't
+/// allowed in a path segment is escaped too.
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Serves every entry of `channel` at `/files/<id>/<name>`, with an index of
+/// links at `/`.
+pub(crate) async fn serve(http: Arc<HttpPool>, channel: u64, address: SocketAddr) -> Result<()> {
+    let listener = TcpListener::bind(address).await?;
+    info!("Listening on http://{}", listener.local_addr()?);
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let http = http.clone();
+
+        tokio::spawn(async move {
+            let service = service_fn(move |req| handle(http.clone(), channel, req));
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                warn!("Connection error: {e}");
+            }
+        });
+    }
+}
+
+async fn handle(
+    http: Arc<HttpPool>,
+    channel: u64,
+    req: Request<Incoming>,
+) -> Result<Response<Body>, Infallible> {
+    info!("{} {}", req.method(), req.uri().path());
+
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return Ok(status(StatusCode::METHOD_NOT_ALLOWED));
+    }
+
+    let path = req.uri().path().to_owned();
+    let res = match path.as_str() {
+        "/" => index(&http, channel).await,
+        _ => match path
+            .strip_prefix("/files/")
+            .and_then(|p| p.split('/').next())
+        {
+            Some(id) => match id.parse() {
+                Ok(id) => file(http, channel, id, &req).await,
+                Err(_) => Ok(status(StatusCode::NOT_FOUND)),
+            },
+            None => Ok(status(StatusCode::NOT_FOUND)),
+        },
+    };
+
+    Ok(res.unwrap_or_else(|e| {
+        warn!("{e}");
+        status(error_status(&e))
+    }))
+}
+
+async fn index(http: &HttpPool, channel: u64) -> Result<Response<Body>> {
+    let mut body = String::from("<!DOCTYPE html>\n<ul>\n");
+    for (entry, id) in list_internal(channel, http).await? {
+        let name = entry.name.unwrap_or_default();
+        let href = utf8_percent_encode(&name, SEGMENT);
+        let name = name
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+            .replace('\'', "&#39;");
+        body += &format!("<li><a href=\"/files/{id}/{href}\">{name}</a></li>\n");
+    }
+    body += "</ul>\n";
+
+    Ok(respond(
+        Response::builder().header(CONTENT_TYPE, "text/html; charset=utf-8"),
+        full(body),
+    ))
+}
+
+async fn file(
+    http: Arc<HttpPool>,
+    channel: u64,
+    id: u64,
+    req: &Request<Incoming>,
+) -> Result<Response<Body>> {
+    let (msg, entry, name, _) = _get_download_variables(&http, id, channel).await?;
+    let size = entry.size.ok_or(DistoreError::IncompleteManifest("size"))?;
+    let part_size = entry.part_size.unwrap_or(PART_SIZE as u64);
+    let mime = mime_guess::from_path(&name).first_or_octet_stream();
+    match entry.compression.as_deref() {
+        None => (),
+        Some(DEFLATE) => return compressed_file(http, msg, &entry, mime.as_ref(), req).await,
+        Some(other) => return Err(DistoreError::UnsupportedCompression(other.to_string())),
+    }
+
+    let range = req.headers().get(RANGE).and_then(|v| v.to_str().ok());
+    let (code, start, end) = match range {
+        None => (StatusCode::OK, 0, size),
+        Some(range) => match parse_range(range, size) {
+            Range::Bytes(start, end) => (StatusCode::PARTIAL_CONTENT, start, end),
+            Range::Unsupported => (StatusCode::OK, 0, size),
+            Range::Unsatisfiable => {
+                return Ok(respond(
+                    Response::builder()
+                        .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                        .header(CONTENT_RANGE, format!("bytes */{size}")),
+                    empty(),
+                ))
+            }
+        },
+    };
+
+    let mut res = Response::builder()
+        .status(code)
+        .header(CONTENT_TYPE, mime.as_ref())
+        .header(CONTENT_LENGTH, end - start)
+        .header(ACCEPT_RANGES, "bytes");
+    if code == StatusCode::PARTIAL_CONTENT {
+        res = res.header(CONTENT_RANGE, format!("bytes {}-{}/{size}", start, end - 1));
+    }
+
+    if req.method() == Method::HEAD {
+        return Ok(respond(res, empty()));
+    }
+
+    let slots = Arc::new(_range_slots(&http, msg, &entry, start, end).await?);
+    let client = http.client().clone();
+
+    // Read one part at a time so seeking never downloads more than it needs
+    let chunks = stream::unfold(start, move |offset| async move {
+        match offset < end {
+            true => {
+                let len = (part_size - offset % part_size).min(end - offset);
+                Some(((offset, len), offset + len))
+            }
+            false => None,
+        }
+    });
+    let body = chunks
+        .then(move |(offset, len)| {
+            let (client, slots) = (client.clone(), slots.clone());
+            async move { _read_slots(&client, &slots, part_size, offset, offset + len).await }
+        })
+        .map_ok(|buf| Frame::data(Bytes::from(buf)));
+
+    Ok(respond(res, BodyExt::boxed(StreamBody::new(body))))
+}
+
+/// Sends a compressed file whole, inflating it on the way. Ranges can't be
+/// served, as there's no telling where an offset lands in the compressed parts.
+/// The length is only sent for uploads that recorded their original size.
+async fn compressed_file(
+    http: Arc<HttpPool>,
+    msg: Message,
+    entry: &FileEntry,
+    mime: &str,
+    req: &Request<Incoming>,
+) -> Result<Response<Body>> {
+    let size = entry.size.ok_or(DistoreError::IncompleteManifest("size"))?;
+    let part_size = entry.part_size.unwrap_or(PART_SIZE as u64);
+
+    let mut res = Response::builder()
+        .header(CONTENT_TYPE, mime)
+        .header(ACCEPT_RANGES, "none");
+    if let Some(original_size) = entry.original_size {
+        res = res.header(CONTENT_LENGTH, original_size);
+    }
+    if req.method() == Method::HEAD {
+        return Ok(respond(res, empty()));
+    }
+
+    let slots = Arc::new(_range_slots(&http, msg, entry, 0, size).await?);
+    let client = http.client().clone();
+
+    let chunks = stream::unfold(0, move |offset| async move {
+        match offset < size {
+            true => {
+                let len = part_size.min(size - offset);
+                Some(((offset, len), offset + len))
+            }
+            false => None,
+        }
+    });
+    let body = chunks
+        .then(move |(offset, len)| {
+            let (client, slots) = (client.clone(), slots.clone());
+            async move {
+                _read_slots(&client, &slots, part_size, offset, offset + len)
+                    .await
+                    .map(Some)
+            }
+        })
+        // Flush what's left in the decoder at the end
+        .chain(stream::once(async { Ok(None) }))
+        .scan(DeflateDecoder::new(Vec::new()), |decoder, chunk| {
+            let buf = chunk.and_then(|chunk| _inflate(decoder, chunk.as_deref()));
+            future::ready(Some(buf))
+        })
+        .map_ok(|buf| Frame::data(Bytes::from(buf)));
+
+    Ok(respond(res, BodyExt::boxed(StreamBody::new(body))))
+}
+
+/// What a `Range` header asks for, as far as it is understood.
+#[derive(Debug, PartialEq)]
+enum Range {
+    /// A half-open interval within the file.
+    Bytes(u64, u64),
+    /// A range that starts past the end of the file.
+    Unsatisfiable,
+    /// Another unit, several ranges or a malformed header, which is ignored
+    /// by serving the whole file.
+    Unsupported,
+}
+
+/// Parses a single `bytes=` range into a half-open interval within `size`.
+fn parse_range(range: &str, size: u64) -> Range {
+    let Some((start, end)) = range
+        .strip_prefix("bytes=")
+        .filter(|r| !r.contains(','))
+        .and_then(|r| r.trim().split_once('-'))
+    else {
+        return Range::Unsupported;
+    };
+    let number = |n: &str| n.parse::<u64>().ok();
+
+    let (start, end) = match (number(start), number(end)) {
+        (None, Some(suffix)) if start.is_empty() => (size.saturating_sub(suffix), size),
+        (Some(start), None) if end.is_empty() => (start, size),
+        (Some(start), Some(end)) if start <= end => (start, size.min(end.saturating_add(1))),
+        _ => return Range::Unsupported,
+    };
+
+    match start < end {
+        true => Range::Bytes(start, end),
+        false => Range::Unsatisfiable,
+    }
+}
+
+fn full(body: impl Into<Bytes>) -> Body {
+    Full::new(body.into()).map_err(|e| match e {}).boxed()
+}
+
+fn empty() -> Body {
+    full(Bytes::new())
+}
+
+/// Finishes a response. The headers set here are all valid, so building it
+/// can't fail.
+fn respond(res: response::Builder, body: Body) -> Response<Body> {
+    res.body(body).expect("Invalid response headers")
+}
+
+/// Status of the response for a request that failed with `e`. Messages that
+/// aren't files are not found, and Discord failing is a bad gateway.
+fn error_status(e: &DistoreError) -> StatusCode {
+    match e {
+        DistoreError::NotAManifest(_)
+        | DistoreError::IncompleteManifest(_)
+        | DistoreError::UncoveredOffset(_)
+        | DistoreError::Parse(_) => StatusCode::NOT_FOUND,
+        _ if e.status() == Some(404) => StatusCode::NOT_FOUND,
+        _ if e.is_rate_limited() => StatusCode::SERVICE_UNAVAILABLE,
+        DistoreError::UnsupportedCompression(_) => StatusCode::NOT_IMPLEMENTED,
+        DistoreError::Discord { .. } | DistoreError::Network(_) | DistoreError::Integrity(_) => {
+            StatusCode::BAD_GATEWAY
+        }
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn status(code: StatusCode) -> Response<Body> {
+    let mut res = Response::new(full(code.to_string()));
+    *res.status_mut() = code;
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_bounds() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Range::Bytes(0, 100));
+        assert_eq!(parse_range("bytes= 10-19", 1000), Range::Bytes(10, 20));
+        // The end is clamped to the size
+        assert_eq!(parse_range("bytes=900-2000", 1000), Range::Bytes(900, 1000));
+    }
+
+    #[test]
+    fn open_ended_and_suffix_ranges() {
+        assert_eq!(parse_range("bytes=400-", 1000), Range::Bytes(400, 1000));
+        assert_eq!(parse_range("bytes=-100", 1000), Range::Bytes(900, 1000));
+        // A suffix longer than the file is the whole file
+        assert_eq!(parse_range("bytes=-5000", 1000), Range::Bytes(0, 1000));
+    }
+
+    #[test]
+    fn unsatisfiable_ranges() {
+        assert_eq!(parse_range("bytes=1000-", 1000), Range::Unsatisfiable);
+        assert_eq!(parse_range("bytes=1500-1600", 1000), Range::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 1000), Range::Unsatisfiable);
+        // Nothing can be read from an empty file
+        assert_eq!(parse_range("bytes=0-", 0), Range::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-10", 0), Range::Unsatisfiable);
+    }
+
+    #[test]
+    fn unsupported_ranges() {
+        assert_eq!(parse_range("bytes=-", 1000), Range::Unsupported);
+        assert_eq!(parse_range("bytes=a-b", 1000), Range::Unsupported);
+        assert_eq!(parse_range("bytes=20-10", 1000), Range::Unsupported);
+        assert_eq!(parse_range("items=0-10", 1000), Range::Unsupported);
+        assert_eq!(parse_range("bytes=0-10,20-30", 1000), Range::Unsupported);
+    }
+}
@@ -0,0 +1,30 @@
+//! Fixtures shared by the tests.
+
+use std::{env, fs, future::Future, path::PathBuf, time::Duration};
+
+use crate::{config::Settings, pool::HttpPool};
+
+/// Creates an empty directory for a test to work in.
+pub(crate) fn scratch(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("distore-{name}-{}", std::process::id()));
+    _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs `future`, failing the test if it takes too long.
+pub(crate) fn run<T>(future: impl Future<Output = T>) -> T {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime
+        .block_on(async { tokio::time::timeout(Duration::from_secs(60), future).await })
+        .expect("timed out")
+}
+
+/// A pool with a single bot that sends its requests to `api_base`.
+pub(crate) fn pool(api_base: String) -> HttpPool {
+    let settings = Settings {
+        api_base: Some(api_base),
+        ..Default::default()
+    };
+    HttpPool::with_settings(&["NQ.x.y"], &settings).unwrap()
+}
@@ -1,7 +1,15 @@
-use std::{io::Write, path::PathBuf};
+use std::{
+    env,
+    io::{self, IsTerminal},
+    net::SocketAddr,
+    path::PathBuf,
+};
 
-use clap::{Parser, Subcommand};
-use libdistore::gui;
+use clap::{builder::PossibleValuesParser, Parser, Subcommand};
+use libdistore::{
+    config::{config_exists, config_path, RemoteArg, Settings},
+    gui,
+};
 
 mod commands;
 
@@ -14,23 +22,78 @@ struct Args {
     /// Custom config directory to use
     #[arg(short, long)]
     config_directory: Option<PathBuf>,
+
+    /// Remote to use, as set in a [remote.<NAME>] section of the config.
+    /// Arguments prefixed with '<NAME>:' take precedence over it
+    #[arg(long, global = true)]
+    remote: Option<String>,
 }
 
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Print or set config values
+    #[command(args_conflicts_with_subcommands = true)]
     Config {
+        #[command(subcommand)]
+        action: Option<ConfigAction>,
+
         /// If present, set the value to be used globally
         #[arg(short, long)]
         global: bool,
 
-        /// Key to be set. Possible keys: token, channel
-        #[arg(requires = "value")]
+        /// Key to be set
+        #[arg(requires = "value", value_parser = PossibleValuesParser::new(Settings::KEYS))]
         key: Option<String>,
         /// Value for the key
         #[arg(requires = "key")]
         value: Option<String>,
+
+        /// Encrypt the token with a passphrase. Every token set afterwards is encrypted too
+        #[arg(long, requires = "value")]
+        encrypt: bool,
+    },
+    /// Checks a bot token, picks a channel for it and saves them both
+    Init {
+        /// Token of the bot. Asked for if not given
+        #[arg(long)]
+        token: Option<String>,
+
+        /// ID of the server to pick a channel from
+        #[arg(long, conflicts_with = "channel")]
+        guild: Option<u64>,
+
+        /// ID of the channel to store files in
+        #[arg(long)]
+        channel: Option<u64>,
+
+        /// Encrypt the token with a passphrase
+        #[arg(long)]
+        encrypt: bool,
+    },
+    /// Creates a private category and channel to store files in, and saves the channel
+    Setup {
+        /// ID of the server to create the channel in
+        #[arg(long)]
+        guild: u64,
+
+        /// ID of a role that can see the channel too. Can be given more than once
+        #[arg(long = "role")]
+        roles: Vec<u64>,
+
+        /// Name of the category
+        #[arg(long, default_value = "Distore")]
+        category: String,
+
+        /// Name of the channel
+        #[arg(long, default_value = "storage")]
+        name: String,
+
+        /// Optionally use a token for this one time
+        #[arg(short, long, require_equals = true)]
+        token: Option<String>,
     },
+    /// Forgets the passphrase of an encrypted config until it is typed again
+    Lock,
     /// Disassemble the file into '.part' files
     Disassemble {
         /// File to be disassembled
@@ -55,8 +118,20 @@ enum Commands {
     },
     /// Uploads a file to Discord
     Upload {
-        /// File to be uploaded
-        file: PathBuf,
+        /// File to be uploaded, optionally prefixed with a remote like 'work:backups/db.sql'
+        file: RemoteArg<PathBuf>,
+
+        /// Keep the parts sent if the upload fails. Uploading the file again continues from them
+        #[arg(long)]
+        keep_partial: bool,
+
+        /// Number of parity parts to upload for every 10 parts, to rebuild missing or corrupt ones
+        #[arg(long, default_value_t = 0)]
+        parity: usize,
+
+        /// Put the file in a thread of its own, or a post in forum channels
+        #[arg(long)]
+        thread: bool,
 
         /// Optionally use a token for this one time
         #[arg(short, long, require_equals = true)]
@@ -68,8 +143,8 @@ enum Commands {
     },
     /// Downloads a file from Discord
     Download {
-        /// Message ID for the file
-        message_id: u64,
+        /// Message ID for the file, optionally prefixed with a remote like 'work:1234'
+        message_id: RemoteArg<u64>,
 
         /// Specifies the output file where the assembled file will be written to
         #[arg(short, long, require_equals = true)]
@@ -83,6 +158,27 @@ enum Commands {
         #[arg(short, long, require_equals = true)]
         channel: Option<u64>,
     },
+    /// Prints a byte range of a file on Discord to stdout
+    Cat {
+        /// Message ID for the file, optionally prefixed with a remote like 'work:1234'
+        message_id: RemoteArg<u64>,
+
+        /// Byte offset to start reading from
+        #[arg(long, default_value_t = 0)]
+        offset: u64,
+
+        /// Number of bytes to read. Defaults to the rest of the file
+        #[arg(long)]
+        length: Option<u64>,
+
+        /// Optionally use a token for this one time
+        #[arg(short, long, require_equals = true)]
+        token: Option<String>,
+
+        /// Optionally use a channel for this one time
+        #[arg(short, long, require_equals = true)]
+        channel: Option<u64>,
+    },
     /// Lists all the files uploaded to the channel
     List {
         /// Optionally use a token for this one time
@@ -93,12 +189,140 @@ enum Commands {
         #[arg(short, long, require_equals = true)]
         channel: Option<u64>,
     },
+    /// Lists the servers and channels the bot can store files in, and how much each one holds
+    Channels {
+        /// Optionally use a token for this one time
+        #[arg(short, long, require_equals = true)]
+        token: Option<String>,
+    },
+    /// Serves the files in the channel over a network protocol
+    Serve {
+        #[command(subcommand)]
+        protocol: ServeProtocol,
+    },
     /// Checks for updates
     Update,
     /// Deletes a file from Discord
     Delete {
-        /// Message ID for the file
-        message_id: u64,
+        /// Message ID for the file, optionally prefixed with a remote like 'work:1234'
+        message_id: RemoteArg<u64>,
+
+        /// Optionally use a token for this one time
+        #[arg(short, long, require_equals = true)]
+        token: Option<String>,
+
+        /// Optionally use a channel for this one time
+        #[arg(short, long, require_equals = true)]
+        channel: Option<u64>,
+    },
+    /// Checks that the files on Discord are intact
+    Verify {
+        /// Message ID for the file, optionally prefixed with a remote like 'work:1234'
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
+        message_id: Option<RemoteArg<u64>>,
+
+        /// Check every file in the channel
+        #[arg(short, long)]
+        all: bool,
+
+        /// Download the files and compare them with their recorded hashes
+        #[arg(short, long)]
+        deep: bool,
+
+        /// Optionally use a token for this one time
+        #[arg(short, long, require_equals = true)]
+        token: Option<String>,
+
+        /// Optionally use a channel for this one time
+        #[arg(short, long, require_equals = true)]
+        channel: Option<u64>,
+    },
+    /// Deletes parts left behind by failed uploads more than an hour ago, except those kept with
+    /// '--keep-partial' on this machine
+    Gc {
+        /// Delete without asking for confirmation
+        #[arg(short, long)]
+        yes: bool,
+
+        /// Optionally use a token for this one time
+        #[arg(short, long, require_equals = true)]
+        token: Option<String>,
+
+        /// Optionally use a channel for this one time
+        #[arg(short, long, require_equals = true)]
+        channel: Option<u64>,
+    },
+    /// Uploads extra copies of a file to other channels
+    Replicate {
+        /// Message ID for the file, optionally prefixed with a remote like 'work:1234'
+        message_id: RemoteArg<u64>,
+
+        /// Channel to put a copy in. Can be given multiple times. Defaults to the configured replicas
+        #[arg(long = "to-channel", require_equals = true)]
+        to_channel: Vec<u64>,
+
+        /// Optionally use a token for this one time
+        #[arg(short, long, require_equals = true)]
+        token: Option<String>,
+
+        /// Optionally use a channel for this one time
+        #[arg(short, long, require_equals = true)]
+        channel: Option<u64>,
+    },
+    /// Copies a file to another channel without downloading it to the disk
+    Cp {
+        /// Message ID for the file, optionally prefixed with a remote like 'work:1234'
+        message_id: RemoteArg<u64>,
+
+        /// Channel to copy the file to. Forum channels are not supported
+        #[arg(long = "to-channel", require_equals = true)]
+        to_channel: u64,
+
+        /// Optionally use a token for this one time
+        #[arg(short, long, require_equals = true)]
+        token: Option<String>,
+
+        /// Optionally use a channel for this one time
+        #[arg(short, long, require_equals = true)]
+        channel: Option<u64>,
+    },
+    /// Moves a file to another channel without downloading it to the disk
+    Mv {
+        /// Message ID for the file, optionally prefixed with a remote like 'work:1234'
+        message_id: RemoteArg<u64>,
+
+        /// Channel to move the file to. Forum channels are not supported
+        #[arg(long = "to-channel", require_equals = true)]
+        to_channel: u64,
+
+        /// Optionally use a token for this one time
+        #[arg(short, long, require_equals = true)]
+        token: Option<String>,
+
+        /// Optionally use a channel for this one time
+        #[arg(short, long, require_equals = true)]
+        channel: Option<u64>,
+    },
+    /// Rebuilds missing or corrupt parts of a file from its parity parts and uploads them again
+    Repair {
+        /// Message ID for the file, optionally prefixed with a remote like 'work:1234'
+        message_id: RemoteArg<u64>,
+
+        /// Optionally use a token for this one time
+        #[arg(short, long, require_equals = true)]
+        token: Option<String>,
+
+        /// Optionally use a channel for this one time
+        #[arg(short, long, require_equals = true)]
+        channel: Option<u64>,
+    },
+    /// Renames a file on Discord without re-uploading it
+    Rename {
+        /// Message ID for the file, optionally prefixed with a remote like 'work:1234'
+        message_id: RemoteArg<u64>,
+
+        /// New name for the file
+        new_name: String,
 
         /// Optionally use a token for this one time
         #[arg(short, long, require_equals = true)]
@@ -110,32 +334,129 @@ enum Commands {
     },
 }
 
-// Convenience macro to read user input
-macro_rules! inputln {
-    ($message:expr) => {{
-        print!("{}: ", $message);
-        std::io::stdout().flush().unwrap();
-        inputln!()
-    }};
-    () => {{
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input).unwrap();
-        input.trim().to_string()
-    }};
+#[derive(Subcommand, Debug)]
+enum ConfigAction {
+    /// Prints the value of a key
+    Get {
+        /// Key to print
+        key: String,
+
+        /// If present, only look at the global value
+        #[arg(short, long)]
+        global: bool,
+    },
+    /// Sets the value of a key
+    Set {
+        /// Key to be set
+        #[arg(value_parser = PossibleValuesParser::new(Settings::KEYS))]
+        key: String,
+
+        /// Value for the key
+        value: String,
+
+        /// If present, set the value to be used globally
+        #[arg(short, long)]
+        global: bool,
+
+        /// Encrypt the token with a passphrase. Every token set afterwards is encrypted too
+        #[arg(long)]
+        encrypt: bool,
+    },
+    /// Removes a key, so that it is inherited again
+    Unset {
+        /// Key to remove
+        key: String,
+
+        /// If present, remove the global value
+        #[arg(short, long)]
+        global: bool,
+    },
+    /// Prints every value that is set
+    List {
+        /// If present, only print the global values
+        #[arg(short, long)]
+        global: bool,
+    },
+    /// Moves the INI config of older versions to distore.toml, checking every value
+    Migrate,
+    /// Lists the directories that have settings of their own
+    Scopes {
+        /// Remove the settings of this directory instead
+        #[arg(long)]
+        remove: Option<PathBuf>,
+    },
+    /// Prints the whole config, or writes it to a file, to be imported elsewhere
+    Export {
+        /// File to write the config to
+        file: Option<PathBuf>,
+
+        /// Leave the tokens out
+        #[arg(long)]
+        no_secrets: bool,
+    },
+    /// Merges an exported config into this one
+    Import {
+        /// File to read the config from, or '-' for stdin
+        file: PathBuf,
+    },
 }
 
-fn first_time_run(args: Args) {
+#[derive(Subcommand, Debug)]
+enum ServeProtocol {
+    /// Serves every file at /files/<ID>/<NAME> with support for range requests
+    Http {
+        /// Address to listen on
+        #[arg(short, long, default_value = "127.0.0.1:8080")]
+        address: SocketAddr,
+
+        /// Optionally use a token for this one time
+        #[arg(short, long, require_equals = true)]
+        token: Option<String>,
+
+        /// Optionally use a channel for this one time
+        #[arg(short, long, require_equals = true)]
+        channel: Option<u64>,
+    },
+}
+
+impl Commands {
+    /// Whether the command needs a token or channel from the config, as
+    /// opposed to not using them or having every one it uses given as flags.
+    fn reads_config(&self) -> bool {
+        use Commands::*;
+
+        match self {
+            Init { .. } | Disassemble { .. } | Assemble { .. } | Update => false,
+            Setup { token, .. } | Channels { token } => token.is_none(),
+            Upload { token, channel, .. }
+            | Download { token, channel, .. }
+            | Cat { token, channel, .. }
+            | List { token, channel, .. }
+            | Delete { token, channel, .. }
+            | Verify { token, channel, .. }
+            | Gc { token, channel, .. }
+            | Replicate { token, channel, .. }
+            | Cp { token, channel, .. }
+            | Mv { token, channel, .. }
+            | Repair { token, channel, .. }
+            | Rename { token, channel, .. }
+            | Serve {
+                protocol: ServeProtocol::Http { token, channel, .. },
+            } => token.is_none() || channel.is_none(),
+            _ => true,
+        }
+    }
+}
+
+async fn first_time_run(args: Args) -> anyhow::Result<()> {
     println!("Looks like it's your first time running.");
     println!(
         "Follow the instructions at https://github.com/obvMellow/distore?tab=readme-ov-file#usage"
     );
-    println!("Then Input your token and channel ID. They will be set automatically for you.");
-
-    let token = inputln!("Token");
-    let channel = inputln!("Channel");
+    println!("Then input your token and pick a channel. They will be set automatically for you.");
 
-    commands::config(true, "token".into(), token, args.config_directory.clone()).unwrap();
-    commands::config(true, "channel".into(), channel, args.config_directory).unwrap();
+    commands::init(None, None, None, false, args.remote, args.config_directory).await?;
+    Ok(())
 }
 
 #[tokio::main]
@@ -143,25 +464,72 @@ async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
 
     if args.command.is_none() {
-        gui::run();
+        gui::run(args.config_directory.clone());
     }
 
-    let config_path = dirs::config_dir()
-        .expect("No config directory found.")
-        .join("distore/distore.ini");
+    let config_path = config_path(args.config_directory.clone())?;
 
-    if !config_path.exists() {
-        first_time_run(args);
-        return Ok(());
+    // Without a config file, the token and channel can still come from the
+    // flags or the environment, so only ask for them on a terminal
+    if !config_exists(&config_path)
+        && env::var_os("DISTORE_TOKEN").is_none()
+        && io::stdin().is_terminal()
+        && args.command.as_ref().is_some_and(Commands::reads_config)
+    {
+        return first_time_run(args).await;
     }
 
     let command = args.command.unwrap();
+    let remote = args.remote;
+    let dir = args.config_directory;
 
     match command {
-        Commands::Config { global, key, value } => match key {
-            Some(key) => commands::config(global, key, value.unwrap(), args.config_directory)?,
-            None => commands::get_config(global, args.config_directory)?,
+        Commands::Config {
+            action: Some(action),
+            ..
+        } => match action {
+            ConfigAction::Get { key, global } => commands::config_get(global, key, remote, dir)?,
+            ConfigAction::Set {
+                key,
+                value,
+                global,
+                encrypt,
+            } => commands::config(global, key, value, encrypt, remote, dir)?,
+            ConfigAction::Unset { key, global } => {
+                commands::config_unset(global, key, remote, dir)?
+            }
+            ConfigAction::List { global } => commands::config_list(global, remote, dir)?,
+            ConfigAction::Migrate => commands::config_migrate(dir)?,
+            ConfigAction::Scopes { remove } => commands::config_scopes(remove, dir)?,
+            ConfigAction::Export { file, no_secrets } => {
+                commands::config_export(file, !no_secrets, dir)?
+            }
+            ConfigAction::Import { file } => commands::config_import(file, dir)?,
+        },
+        Commands::Config {
+            global,
+            key,
+            value,
+            encrypt,
+            ..
+        } => match key {
+            Some(key) => commands::config(global, key, value.unwrap(), encrypt, remote, dir)?,
+            None => commands::get_config(global, remote, dir)?,
         },
+        Commands::Init {
+            token,
+            guild,
+            channel,
+            encrypt,
+        } => commands::init(token, guild, channel, encrypt, remote, dir).await?,
+        Commands::Setup {
+            guild,
+            roles,
+            category,
+            name,
+            token,
+        } => commands::setup(guild, roles, category, name, token, remote, dir).await?,
+        Commands::Lock => commands::lock()?,
         Commands::Disassemble {
             file,
             output_directory,
@@ -173,24 +541,140 @@ async fn main() -> anyhow::Result<()> {
         } => commands::assemble(file_name, parts, output)?,
         Commands::Upload {
             file,
+            keep_partial,
+            parity,
+            thread,
             token,
             channel,
-        } => commands::upload(file, token, channel, args.config_directory).await?,
+        } => {
+            let options = commands::UploadOptions {
+                parity,
+                thread,
+                keep_partial,
+                ..Default::default()
+            };
+            let file = file.resolve(&config_path);
+            let remote = file.remote.or(remote);
+            commands::upload(file.value, options, token, channel, remote, dir).await?
+        }
         Commands::Download {
             message_id,
             output,
             token,
             channel,
-        } => commands::download(message_id, token, channel, args.config_directory, output).await?,
-        Commands::List { token, channel } => {
-            commands::list(token, channel, args.config_directory).await?
+        } => {
+            let message_id = message_id.resolve(&config_path);
+            let remote = message_id.remote.or(remote);
+            commands::download(message_id.value, token, channel, remote, dir, output).await?
+        }
+        Commands::Cat {
+            message_id,
+            offset,
+            length,
+            token,
+            channel,
+        } => {
+            let message_id = message_id.resolve(&config_path);
+            let remote = message_id.remote.or(remote);
+            commands::cat(
+                message_id.value,
+                offset,
+                length,
+                token,
+                channel,
+                remote,
+                dir,
+            )
+            .await?
         }
+        Commands::List { token, channel } => commands::list(token, channel, remote, dir).await?,
+        Commands::Channels { token } => commands::channels(token, remote, dir).await?,
+        Commands::Serve { protocol } => match protocol {
+            ServeProtocol::Http {
+                address,
+                token,
+                channel,
+            } => commands::serve_http(address, token, channel, remote, dir).await?,
+        },
         Commands::Update => commands::check_update().await?,
         Commands::Delete {
             message_id,
             token,
             channel,
-        } => commands::delete(message_id, token, channel, args.config_directory).await?,
+        } => {
+            let message_id = message_id.resolve(&config_path);
+            let remote = message_id.remote.or(remote);
+            commands::delete(message_id.value, token, channel, remote, dir).await?
+        }
+        Commands::Verify {
+            message_id,
+            all: _,
+            deep,
+            token,
+            channel,
+        } => {
+            let message_id = message_id.map(|m| m.resolve(&config_path));
+            let remote = message_id
+                .as_ref()
+                .and_then(|m| m.remote.clone())
+                .or(remote);
+            let message_id = message_id.map(|m| m.value);
+            commands::verify(message_id, deep, token, channel, remote, dir).await?
+        }
+        Commands::Gc {
+            yes,
+            token,
+            channel,
+        } => commands::gc(yes, token, channel, remote, dir).await?,
+        Commands::Replicate {
+            message_id,
+            to_channel,
+            token,
+            channel,
+        } => {
+            let message_id = message_id.resolve(&config_path);
+            let remote = message_id.remote.or(remote);
+            commands::replicate(message_id.value, to_channel, token, channel, remote, dir).await?
+        }
+        Commands::Cp {
+            message_id,
+            to_channel,
+            token,
+            channel,
+        } => {
+            let message_id = message_id.resolve(&config_path);
+            let remote = message_id.remote.or(remote);
+            commands::cp(message_id.value, to_channel, token, channel, remote, dir).await?
+        }
+        Commands::Mv {
+            message_id,
+            to_channel,
+            token,
+            channel,
+        } => {
+            let message_id = message_id.resolve(&config_path);
+            let remote = message_id.remote.or(remote);
+            commands::mv(message_id.value, to_channel, token, channel, remote, dir).await?
+        }
+        Commands::Repair {
+            message_id,
+            token,
+            channel,
+        } => {
+            let message_id = message_id.resolve(&config_path);
+            let remote = message_id.remote.or(remote);
+            commands::repair(message_id.value, token, channel, remote, dir).await?
+        }
+        Commands::Rename {
+            message_id,
+            new_name,
+            token,
+            channel,
+        } => {
+            let message_id = message_id.resolve(&config_path);
+            let remote = message_id.remote.or(remote);
+            commands::rename(message_id.value, new_name, token, channel, remote, dir).await?
+        }
     }
 
     Ok(())